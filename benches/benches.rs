@@ -2,12 +2,13 @@ use std::hint::black_box;
 use std::path::Path;
 
 use criterion::{Criterion, criterion_group, criterion_main};
-use schiller_lib::db::{AtomicDatabase, BookSearch};
+use schiller_lib::db::{AtomicDatabase, BookSearch, StorageKind};
 use schiller_lib::util;
 
 fn criterion_benchmark(c: &mut Criterion) {
     util::logging();
-    let db = AtomicDatabase::load(Path::new("test/demo.json"), None).unwrap();
+    let db =
+        AtomicDatabase::load(Path::new("test/demo.json"), None, StorageKind::Json, false).unwrap();
     let db = db.read();
 
     let search = BookSearch {
@@ -57,7 +57,7 @@ function show_error(error: api.Error): never {
 }
 
 /** Server Error translations */
-function error_msg(error: api.Error): string {
+export function error_msg(error: api.Error): string {
 	switch (error) {
 		case api.Error.Arguments:
 			return '.error.input';
@@ -111,3 +111,8 @@ export function mail_replace(
 		body: template.body.replaceAll('{booktitle}', booktitle).replaceAll('{username}', username)
 	};
 }
+
+/** Formats the authors as "Forename Surname, ..." */
+export function author_names(authors: api.Author[]): string {
+	return authors.map((a) => (a.forename ? `${a.forename} ${a.surname}` : a.surname)).join(', ');
+}
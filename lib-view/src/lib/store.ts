@@ -13,6 +13,16 @@ export interface GlobalSettings {
 	mail_info: api.MailTemplate;
 	mail_overdue: api.MailTemplate;
 	mail_overdue2: api.MailTemplate;
+	providers: api.Provider[];
+	id_scheme: api.IdScheme;
+	id_prefix: string;
+	id_digits: number;
+	id_pattern: string;
+	weeding_age: number;
+	weeding_idle: number;
+	weeding_copies: number;
+	catalogue_public: boolean;
+	catalogues: api.RemoteCatalogue[];
 }
 
 export const settingsGlobal = writable<GlobalSettings>({
@@ -33,7 +43,17 @@ export const settingsGlobal = writable<GlobalSettings>({
 	mail_overdue2: {
 		subject: '',
 		body: ''
-	}
+	},
+	providers: [],
+	id_scheme: api.IdScheme.Category,
+	id_prefix: '',
+	id_digits: 6,
+	id_pattern: '',
+	weeding_age: 10,
+	weeding_idle: 5,
+	weeding_copies: 3,
+	catalogue_public: false,
+	catalogues: []
 });
 
 /// UI state of the book tab
@@ -1,6 +1,6 @@
 use std::path::Path;
 
-use schiller_lib::db::{AtomicDatabase, Book, Category};
+use schiller_lib::db::{AtomicDatabase, Book, Category, Condition, StorageKind};
 use schiller_lib::error::Error;
 use schiller_lib::{provider, util};
 use tracing::error;
@@ -27,7 +27,7 @@ async fn main() {
     }
     println!("=> {} books", books.len());
 
-    let db = AtomicDatabase::create(Path::new("test/demo.json")).unwrap();
+    let db = AtomicDatabase::create(Path::new("test/demo.json"), StorageKind::Json).unwrap();
     let mut db = db.write();
     db.categories
         .add(Category {
@@ -58,12 +58,24 @@ async fn main() {
             costs: record.data.costs,
             note: String::new(),
             borrowable: true,
-            authors: record.data.authors.join(", "),
+            authors: record.data.authors,
             borrower: None,
             reservation: None,
+            edition: record.data.edition,
+            language: record.data.language,
+            pages: record.data.pages,
+            series: record.data.series,
+            subjects: record.data.subjects,
+            ddc: record.data.ddc,
+            cover: record.data.cover,
+            added: None,
+            condition: Condition::Good,
         };
         let db = &mut *db;
-        let id = db.books.generate_id(&book).unwrap();
+        let id = db
+            .books
+            .generate_id(&book, &db.settings(), &db.categories)
+            .unwrap();
         book.id = id;
         if book.validate() {
             // ignore duplicates
@@ -0,0 +1,331 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, Local, NaiveDateTime, Timelike};
+use gluer::metadata;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use super::{Database, migrate};
+use crate::error::{Error, Result};
+
+/// Number of kept snapshots per day, week, and month
+const KEEP_DAILY: usize = 7;
+const KEEP_WEEKLY: usize = 4;
+const KEEP_MONTHLY: usize = 12;
+
+const TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Reason for taking a snapshot
+#[metadata]
+#[repr(i64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BackupKind {
+    /// Regular daily snapshot
+    Daily = 0,
+    /// Requested by a user
+    Manual,
+    /// Before migrating to a new version
+    Migration,
+    /// Before importing data
+    Import,
+    /// Before restoring another snapshot
+    Restore,
+    /// Before repairing integrity problems
+    Repair,
+}
+
+impl BackupKind {
+    const ALL: [BackupKind; 6] = [
+        BackupKind::Daily,
+        BackupKind::Manual,
+        BackupKind::Migration,
+        BackupKind::Import,
+        BackupKind::Restore,
+        BackupKind::Repair,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            BackupKind::Daily => "daily",
+            BackupKind::Manual => "manual",
+            BackupKind::Migration => "migration",
+            BackupKind::Import => "import",
+            BackupKind::Restore => "restore",
+            BackupKind::Repair => "repair",
+        }
+    }
+}
+
+/// Snapshot of the database
+#[metadata]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Backup {
+    /// File name, used to download or restore the snapshot
+    pub name: String,
+    #[meta(into = String)]
+    pub created: NaiveDateTime,
+    pub kind: BackupKind,
+    /// File size in bytes
+    pub size: u64,
+}
+
+/// Directory of timestamped database snapshots.
+///
+/// The snapshots are named `<db>-<YYYYmmdd-HHMMSS>-<kind>.json`.
+#[derive(Debug)]
+pub struct Backups {
+    dir: PathBuf,
+    prefix: String,
+}
+
+impl Backups {
+    /// Snapshots of the database at `path`, stored in a `backups` directory next to it.
+    pub fn new(path: &Path) -> Self {
+        let prefix = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "db".into());
+        Self {
+            dir: path.with_file_name("backups"),
+            prefix,
+        }
+    }
+
+    /// Returns all snapshots, the newest first.
+    pub fn list(&self) -> Result<Vec<Backup>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut backups = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some((created, kind)) = self.parse(&name) {
+                backups.push(Backup {
+                    name,
+                    created,
+                    kind,
+                    size: entry.metadata()?.len(),
+                });
+            }
+        }
+        backups.sort_by(|a, b| b.created.cmp(&a.created).then(b.name.cmp(&a.name)));
+        Ok(backups)
+    }
+
+    /// Writes a snapshot of the database.
+    pub fn create(&self, db: &Database, kind: BackupKind) -> Result<Backup> {
+        self.write(kind, |file| db.save(file))
+    }
+
+    /// Copies the database file as it is, e.g., before it is migrated.
+    pub fn copy(&self, path: &Path, kind: BackupKind) -> Result<Backup> {
+        self.write(kind, |file| {
+            std::io::copy(&mut File::open(path)?, file)?;
+            Ok(())
+        })
+    }
+
+    /// Returns the path of the snapshot, if it exists.
+    pub fn path(&self, name: &str) -> Result<PathBuf> {
+        // only accept our own file names, nothing outside the directory
+        if self.parse(name).is_none() || name.contains(['/', '\\']) {
+            return Err(Error::Arguments);
+        }
+        let path = self.dir.join(name);
+        if path.is_file() {
+            Ok(path)
+        } else {
+            Err(Error::NothingFound)
+        }
+    }
+
+    /// Loads and validates the snapshot, migrating it if necessary.
+    pub fn load(&self, name: &str) -> Result<Database> {
+        let path = self.path(name)?;
+        let mut db = migrate::import(&path, None)?;
+        db.changes();
+        Ok(db)
+    }
+
+    /// Takes the daily snapshot if there is none of today.
+    ///
+    /// Returns the new snapshot, if any.
+    pub fn daily(&self, db: &Database) -> Result<Option<Backup>> {
+        let today = Local::now().date_naive();
+        let backups = self.list()?;
+        if backups.iter().any(|b| b.created.date() == today) {
+            return Ok(None);
+        }
+        let backup = self.create(db, BackupKind::Daily)?;
+        self.prune()?;
+        Ok(Some(backup))
+    }
+
+    /// Deletes the snapshots that are not covered by the retention rules.
+    ///
+    /// Returns the number of deleted snapshots.
+    pub fn prune(&self) -> Result<usize> {
+        let backups = self.list()?;
+        let keep = retain(&backups);
+        let mut deleted = 0;
+        for backup in &backups {
+            if !keep.contains(backup.name.as_str()) {
+                info!("Deleting backup {}", backup.name);
+                fs::remove_file(self.dir.join(&backup.name))?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    fn write(&self, kind: BackupKind, f: impl FnOnce(&mut File) -> Result<()>) -> Result<Backup> {
+        fs::create_dir_all(&self.dir)?;
+        // the name only contains seconds
+        let created = Local::now().naive_local().with_nanosecond(0).unwrap();
+        let name = format!(
+            "{}-{}-{}.json",
+            self.prefix,
+            created.format(TIME_FORMAT),
+            kind.name()
+        );
+        let path = self.dir.join(&name);
+        let tmp = self.dir.join(format!(".{name}~"));
+        {
+            let mut file = File::create(&tmp)?;
+            f(&mut file)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path)?;
+        info!("Created backup {name}");
+        Ok(Backup {
+            size: fs::metadata(&path)?.len(),
+            name,
+            created,
+            kind,
+        })
+    }
+
+    fn parse(&self, name: &str) -> Option<(NaiveDateTime, BackupKind)> {
+        let rest = name.strip_prefix(&self.prefix)?.strip_prefix('-')?;
+        let rest = rest.strip_suffix(".json")?;
+        let (time, kind) = rest.rsplit_once('-')?;
+        let kind = BackupKind::ALL.into_iter().find(|k| k.name() == kind)?;
+        match NaiveDateTime::parse_from_str(time, TIME_FORMAT) {
+            Ok(created) => Some((created, kind)),
+            Err(_) => {
+                warn!("Invalid backup name {name}");
+                None
+            }
+        }
+    }
+}
+
+/// Names of the snapshots to keep: the newest of each of the last
+/// 7 days, 4 weeks, and 12 months that have a snapshot.
+///
+/// The `backups` have to be ordered by their creation, the newest first.
+fn retain(backups: &[Backup]) -> HashSet<&str> {
+    let mut keep = HashSet::new();
+    let mut days = BTreeSet::new();
+    let mut weeks = BTreeSet::new();
+    let mut months = BTreeSet::new();
+    for backup in backups {
+        let date = backup.created.date();
+        let week = date.iso_week();
+        // each rule has to see every snapshot
+        let daily = days.len() < KEEP_DAILY && days.insert(date);
+        let weekly = weeks.len() < KEEP_WEEKLY && weeks.insert((week.year(), week.week()));
+        let monthly = months.len() < KEEP_MONTHLY && months.insert((date.year(), date.month()));
+        if daily || weekly || monthly {
+            keep.insert(backup.name.as_str());
+        }
+    }
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::{Days, NaiveDate};
+
+    use super::*;
+    use crate::db::Category;
+
+    #[test]
+    fn retention() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        // two snapshots per day over a year, the newest first
+        let backups = (0..366 * 2)
+            .rev()
+            .map(|i| {
+                let date = start + Days::new(i / 2);
+                let created = date.and_hms_opt(if i % 2 == 0 { 6 } else { 18 }, 0, 0);
+                Backup {
+                    name: format!("lib-{i}"),
+                    created: created.unwrap(),
+                    kind: BackupKind::Daily,
+                    size: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let keep = retain(&backups);
+        let mut kept = backups
+            .iter()
+            .filter(|b| keep.contains(b.name.as_str()))
+            .map(|b| b.created.to_string())
+            .collect::<Vec<_>>();
+        kept.reverse();
+        // the newest of each of the 12 months, and the weeks before the last 7 days
+        assert_eq!(kept.len(), 11 + 2 + 7);
+        assert_eq!(kept[0], "2024-01-31 18:00:00");
+        assert_eq!(kept[10], "2024-11-30 18:00:00");
+        assert_eq!(kept[11], "2024-12-15 18:00:00");
+        assert_eq!(kept[12], "2024-12-22 18:00:00");
+        assert_eq!(kept[13], "2024-12-25 18:00:00");
+        assert_eq!(kept[19], "2024-12-31 18:00:00");
+
+        assert!(retain(&[]).is_empty());
+    }
+
+    #[test]
+    fn snapshots() {
+        let dir = env::temp_dir().join(format!("schiller-backup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let backups = Backups::new(&dir.join("lib.json"));
+        assert!(backups.list().unwrap().is_empty());
+
+        let mut db = Database::default();
+        db.categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: String::new(),
+            })
+            .unwrap();
+        let daily = backups.daily(&db).unwrap().unwrap();
+        assert_eq!(daily.kind, BackupKind::Daily);
+        assert!(backups.daily(&db).unwrap().is_none());
+        assert_eq!(backups.list().unwrap(), std::slice::from_ref(&daily));
+
+        let loaded = backups.load(&daily.name).unwrap();
+        assert!(loaded.categories.data.contains_key("FANT"));
+
+        // only snapshot names are accepted
+        assert!(matches!(backups.load("../lib.json"), Err(Error::Arguments)));
+        assert!(matches!(
+            backups.load("lib-20000101-000000-daily.json"),
+            Err(Error::NothingFound)
+        ));
+        fs::write(dir.join("backups/lib-20000101-000000-manual.json"), "{}").unwrap();
+        assert!(backups.load("lib-20000101-000000-manual.json").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,16 +1,20 @@
-use std::collections::BTreeMap;
 use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-use chrono::NaiveDate;
+use chrono::{Local, NaiveDate};
 use gluer::metadata;
 use serde::{Deserialize, Serialize};
-use unicode_normalization::UnicodeNormalization;
 
-use super::{Categories, Users};
+use super::{Categories, IdChange, IdTemplate, Settings, Users, id_scheme};
 use crate::db::sorted::Sorted;
 use crate::error::{Error, Result};
+use crate::fuzzy::Fuzzy;
 use crate::isbn;
 
+/// Minimal fuzzy score of title and authors, relative to an exact match in
+/// percent, for a book to be a possible duplicate
+const DUPLICATE_SIMILARITY: u32 = 90;
+
 /// Data object for book.
 #[metadata]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,13 +31,48 @@ pub struct Book {
     pub note: String,
     pub borrowable: bool,
     pub category: String,
-    pub authors: String,
+    pub authors: Vec<Author>,
     #[meta(optional, into = Borrower)]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub borrower: Option<Borrower>,
     #[meta(optional, into = String)]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub reservation: Option<String>,
+    #[meta(optional)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub edition: String,
+    #[meta(optional)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub language: String,
+    #[meta(optional)]
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub pages: u32,
+    #[meta(optional)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub series: String,
+    #[meta(optional)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subjects: Vec<String>,
+    /// Dewey Decimal Classification
+    #[meta(optional)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub ddc: String,
+    /// URL of a cover image
+    #[meta(optional)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub cover: String,
+    /// Date the book was added to the library
+    #[meta(optional, into = String)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added: Option<NaiveDate>,
+    /// Physical state of the book
+    #[meta(optional)]
+    #[serde(default, skip_serializing_if = "Condition::is_good")]
+    pub condition: Condition,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
 }
 
 #[metadata]
@@ -45,6 +84,99 @@ pub struct Borrower {
     pub deadline: NaiveDate,
 }
 
+/// Lowercase name particles that are part of the surname, e.g., "de la Cruz"
+const NAME_PARTICLES: [&str; 15] = [
+    "de", "la", "le", "du", "da", "di", "del", "van", "von", "der", "den", "ten", "ter", "zu",
+    "zur",
+];
+
+/// Person that contributed to a book
+#[metadata]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Author {
+    pub surname: String,
+    #[meta(optional)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub forename: String,
+    #[serde(default)]
+    pub role: AuthorRole,
+}
+
+/// Contribution of an author to a book
+#[metadata]
+#[repr(i64)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthorRole {
+    /// Wrote the book
+    #[default]
+    Author = 0,
+    /// Edited or compiled the book
+    Editor,
+    /// Created the illustrations
+    Illustrator,
+}
+
+impl Author {
+    /// Parses "<surname>, <forename>" or "<forename> <surname>".
+    ///
+    /// Lowercase particles before the surname ("von", "de la") are kept
+    /// with the surname.
+    pub fn parse(name: &str) -> Self {
+        if let Some((surname, forename)) = name.split_once(',') {
+            return Self {
+                surname: surname.trim().into(),
+                forename: forename.trim().into(),
+                role: AuthorRole::Author,
+            };
+        }
+        let words = name.split_whitespace().collect::<Vec<_>>();
+        let mut start = words.len().saturating_sub(1);
+        while start > 1 && NAME_PARTICLES.contains(&words[start - 1]) {
+            start -= 1;
+        }
+        Self {
+            surname: words[start..].join(" "),
+            forename: words[..start].join(" "),
+            role: AuthorRole::Author,
+        }
+    }
+
+    /// Full name in the "<forename> <surname>" form.
+    pub fn name(&self) -> String {
+        if self.forename.is_empty() {
+            self.surname.clone()
+        } else {
+            format!("{} {}", self.forename, self.surname)
+        }
+    }
+
+    /// Surname without the leading particles, used for sorting and ids.
+    pub fn sort_name(&self) -> &str {
+        let mut surname = self.surname.as_str();
+        while let Some((particle, rest)) = surname.split_once(' ')
+            && particle.starts_with(char::is_lowercase)
+        {
+            surname = rest.trim_start();
+        }
+        surname
+    }
+
+    fn validate(&mut self) -> bool {
+        self.surname = self.surname.trim().to_string();
+        self.forename = self.forename.trim().to_string();
+        if self.surname.is_empty() {
+            self.surname = std::mem::take(&mut self.forename);
+        }
+        !self.surname.is_empty()
+    }
+}
+
+impl From<&str> for Author {
+    fn from(name: &str) -> Self {
+        Self::parse(name)
+    }
+}
+
 impl Book {
     /// Check if the book is valid
     pub fn validate(&mut self) -> bool {
@@ -54,7 +186,16 @@ impl Book {
         self.publisher = self.publisher.trim().to_string();
         self.note = self.note.trim().to_string();
         self.category = self.category.trim().to_string();
-        self.authors = self.authors.trim().to_string();
+        self.authors.retain_mut(Author::validate);
+        self.edition = self.edition.trim().to_string();
+        self.language = self.language.trim().to_string();
+        self.series = self.series.trim().to_string();
+        self.subjects.retain_mut(|s| {
+            *s = s.trim().to_string();
+            !s.is_empty()
+        });
+        self.ddc = self.ddc.trim().to_string();
+        self.cover = self.cover.trim().to_string();
         if let Some(reservation) = &mut self.reservation {
             *reservation = reservation.trim().to_string();
         }
@@ -64,18 +205,45 @@ impl Book {
         !self.id.is_empty() && !self.title.is_empty()
     }
 
+    /// Check if the borrower and the reservation refer to existing users
+    pub fn users_exist(&self, users: &Users) -> bool {
+        self.borrower
+            .as_ref()
+            .is_none_or(|b| users.data.contains_key(&b.user))
+            && self
+                .reservation
+                .as_ref()
+                .is_none_or(|r| users.data.contains_key(r))
+    }
+
+    /// Names of the authors, separated by commas
+    pub fn author_names(&self) -> String {
+        let names = self.authors.iter().map(Author::name);
+        names.collect::<Vec<_>>().join(", ")
+    }
+
+    /// Surnames of the authors, separated by commas
+    fn surnames(&self) -> String {
+        let names = self.authors.iter().map(|a| a.surname.as_str());
+        names.collect::<Vec<_>>().join(", ")
+    }
+
     /// Fuzzy search score for this book
-    pub fn fuzzy(&self, fuzzy: &mut crate::fuzzy::Fuzzy) -> u32 {
-        fuzzy.score_many(&[
+    pub fn fuzzy(&self, fuzzy: &mut Fuzzy) -> u32 {
+        let mut score = fuzzy.score_many(&[
             (self.id.as_str(), 1), // <- exact match is handled separately
             (self.title.as_str(), 3),
-            (self.authors.as_str(), 2),
             (self.isbn.as_str(), 1),
             (self.publisher.as_str(), 1),
             (self.note.as_str(), 1),
+            (self.series.as_str(), 1),
             (self.borrower.as_ref().map_or("", |b| b.user.as_str()), 1),
             (self.reservation.as_deref().unwrap_or(""), 1),
-        ])
+        ]);
+        for author in &self.authors {
+            score += fuzzy.score_many(&[(&author.surname, 2), (&author.forename, 1)]);
+        }
+        score
     }
 }
 
@@ -103,6 +271,26 @@ impl Default for BookSearch {
     }
 }
 
+/// Physical state of a book
+#[metadata]
+#[repr(i64)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Condition {
+    /// Available in the library
+    #[default]
+    Good = 0,
+    /// Worn out or damaged, e.g., a candidate for weeding
+    Damaged,
+    /// Missing, e.g., not found in the inventory
+    Lost,
+}
+
+impl Condition {
+    fn is_good(&self) -> bool {
+        *self == Condition::Good
+    }
+}
+
 /// Borrow status of a book
 #[metadata]
 #[repr(i64)]
@@ -119,16 +307,31 @@ pub enum BookState {
     Borrowed,
     /// Is already reserved
     Reserved,
+    /// Is marked as lost
+    Lost,
 }
 
 /// Container for all book
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Books {
+    /// Direct modifications have to be recorded with [`Books::touch`]
     #[serde(flatten)]
     pub data: BTreeMap<String, Book>,
+    /// Ids of the modified books, that are not yet saved
+    #[serde(skip)]
+    changed: BTreeSet<String>,
 }
 
 impl Books {
+    /// Record the modification of the book, to be saved with the next commit.
+    pub fn touch(&mut self, id: &str) {
+        self.changed.insert(id.into());
+    }
+    /// Returns and resets the ids of the modified books.
+    pub(super) fn take_changed(&mut self) -> BTreeSet<String> {
+        std::mem::take(&mut self.changed)
+    }
+
     /// Return the book with this `id`
     pub fn fetch(&self, id: &str) -> Result<Book> {
         let id = id.trim();
@@ -141,23 +344,19 @@ impl Books {
 
     /// Add a new book
     pub fn add(&mut self, mut book: Book, categories: &Categories, users: &Users) -> Result<Book> {
-        if !book.validate() || !categories.data.contains_key(&book.category) {
-            return Err(Error::InvalidBook);
-        }
-        if let Some(borrower) = &book.borrower
-            && !users.data.contains_key(&borrower.user)
-        {
-            return Err(Error::InvalidBook);
-        }
-        if let Some(reservation) = &book.reservation
-            && !users.data.contains_key(reservation)
+        if !book.validate()
+            || !categories.data.contains_key(&book.category)
+            || !book.users_exist(users)
         {
             return Err(Error::InvalidBook);
         }
 
+        book.added.get_or_insert_with(|| Local::now().date_naive());
+
         match self.data.entry(book.id.clone()) {
             Entry::Vacant(v) => {
                 v.insert(book.clone());
+                self.touch(&book.id);
                 Ok(book)
             }
             _ => Err(Error::Duplicate),
@@ -165,15 +364,30 @@ impl Books {
     }
 
     /// Update the book data
-    pub fn update(&mut self, id: &str, mut book: Book, categories: &Categories) -> Result<Book> {
+    pub fn update(
+        &mut self,
+        id: &str,
+        mut book: Book,
+        categories: &Categories,
+        users: &Users,
+    ) -> Result<Book> {
         let id = id.trim();
-        if id.is_empty() || !book.validate() || !categories.data.contains_key(&book.category) {
+        if id.is_empty()
+            || !book.validate()
+            || !categories.data.contains_key(&book.category)
+            || !book.users_exist(users)
+        {
             return Err(Error::InvalidBook);
         }
 
+        if book.added.is_none() {
+            book.added = self.data.get(id).and_then(|b| b.added);
+        }
+
         if id == book.id {
             if let Some(entry) = self.data.get_mut(id) {
                 *entry = book.clone();
+                self.touch(id);
                 return Ok(book);
             }
         } else if self.data.contains_key(id) {
@@ -181,6 +395,8 @@ impl Books {
                 Entry::Vacant(v) => {
                     v.insert(book.clone());
                     self.data.remove(id);
+                    self.touch(id);
+                    self.touch(&book.id);
                     Ok(book)
                 }
                 _ => Err(Error::Duplicate),
@@ -193,15 +409,130 @@ impl Books {
     /// Delete the corresponding book
     pub fn delete(&mut self, id: &str) -> Result<()> {
         let id = id.trim();
-        if !id.is_empty() {
-            self.data.remove(id).map(|_| ()).ok_or(Error::NothingFound)
-        } else {
-            Err(Error::Arguments)
+        if id.is_empty() {
+            return Err(Error::Arguments);
+        }
+        self.data.remove(id).ok_or(Error::NothingFound)?;
+        self.touch(id);
+        Ok(())
+    }
+
+    /// Returns the other books with the same isbn (in either form) or
+    /// similar title and authors.
+    ///
+    /// Without authors, only the title is compared.
+    pub fn duplicates(&self, book: &Book) -> Vec<Book> {
+        // empty patterns cannot be scored
+        let matcher = |text: &str| {
+            let text = text.trim();
+            (!text.is_empty()).then(|| {
+                let mut fuzzy = Fuzzy::new(text);
+                let max = fuzzy.score(text);
+                (fuzzy, max)
+            })
+        };
+        let mut title = matcher(&book.title);
+        let mut authors = matcher(&book.surnames());
+
+        self.data
+            .values()
+            .filter(|other| other.id != book.id.trim())
+            .filter(|other| {
+                isbn::same(&other.isbn, &book.isbn)
+                    || title.as_mut().is_some_and(|(title, max)| {
+                        title.score(&other.title) * 100 >= *max * DUPLICATE_SIMILARITY
+                    }) && authors.as_mut().is_none_or(|(authors, max)| {
+                        authors.score(&other.surnames()) * 100 >= *max * DUPLICATE_SIMILARITY
+                    })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Merges the `source` book into the book with the given `id`.
+    ///
+    /// Empty fields are filled with the ones from `source`, which is deleted afterwards.
+    /// Fails if both books are borrowed or reserved.
+    /// Use [`super::Database::merge_books`] to also move the loans.
+    pub fn merge(&mut self, id: &str, source: &str) -> Result<Book> {
+        let (id, source) = (id.trim(), source.trim());
+        if id.is_empty() || source.is_empty() || id == source {
+            return Err(Error::Arguments);
+        }
+        let other = self.fetch(source)?;
+        let book = self.data.get_mut(id).ok_or(Error::NothingFound)?;
+        if book.borrower.is_some() && other.borrower.is_some() {
+            return Err(Error::LendingBookAlreadyBorrowed);
+        }
+        if book.reservation.is_some() && other.reservation.is_some() {
+            return Err(Error::LendingBookAlreadyReserved);
+        }
+
+        fn fill(field: &mut String, other: String) {
+            if field.is_empty() {
+                *field = other;
+            }
+        }
+        fill(&mut book.isbn, other.isbn);
+        fill(&mut book.title, other.title);
+        fill(&mut book.publisher, other.publisher);
+        fill(&mut book.edition, other.edition);
+        fill(&mut book.language, other.language);
+        fill(&mut book.series, other.series);
+        fill(&mut book.ddc, other.ddc);
+        fill(&mut book.cover, other.cover);
+        if book.authors.is_empty() {
+            book.authors = other.authors;
+        }
+        if book.year == 0 {
+            book.year = other.year;
+        }
+        if book.costs == 0.0 {
+            book.costs = other.costs;
+        }
+        if book.pages == 0 {
+            book.pages = other.pages;
+        }
+        for subject in other.subjects {
+            if !book.subjects.contains(&subject) {
+                book.subjects.push(subject);
+            }
+        }
+        if !other.note.is_empty() && !book.note.contains(&other.note) {
+            if !book.note.is_empty() {
+                book.note.push('\n');
+            }
+            book.note.push_str(&other.note);
         }
+        book.borrower = book.borrower.take().or(other.borrower);
+        book.reservation = book.reservation.take().or(other.reservation);
+
+        let book = book.clone();
+        self.data.remove(source);
+        self.touch(id);
+        self.touch(source);
+        Ok(book)
     }
 
     /// Search specific books
     pub fn search(&self, search: &BookSearch) -> Result<(usize, Vec<Book>)> {
+        let results = self.ranked(search);
+        let total = results.len();
+
+        let books = results
+            .into_iter()
+            .skip(search.offset)
+            .take(search.limit)
+            .map(|(_, b)| b.clone())
+            .collect();
+
+        Ok((total, books))
+    }
+
+    /// All books that match the search, with their scores, the best first.
+    ///
+    /// Exact id or isbn matches have the maximum score.
+    pub fn ranked(&self, search: &BookSearch) -> Vec<(u32, &Book)> {
         let mut results = Sorted::<(u32, String, &Book), _>::new(|a, b| {
             a.0.cmp(&b.0)
                 .reverse()
@@ -210,7 +541,9 @@ impl Books {
         });
 
         let query = search.query.trim().to_lowercase();
-        let mut fuzzy = (!query.is_empty()).then(|| crate::fuzzy::Fuzzy::new(&query));
+        let mut fuzzy = (!query.is_empty()).then(|| Fuzzy::new(&query));
+        // Match isbn-10 and isbn-13 forms
+        let query_isbn = isbn::to_isbn13(&query);
 
         // just a very basic keyword search
         for book in self.data.values() {
@@ -225,6 +558,7 @@ impl Books {
                 BookState::NotBorrowable if book.borrowable => continue,
                 BookState::Borrowed if book.borrower.is_none() => continue,
                 BookState::Reserved if book.reservation.is_none() => continue,
+                BookState::Lost if book.condition != Condition::Lost => continue,
                 _ => {}
             }
 
@@ -232,7 +566,9 @@ impl Books {
 
             // Exact match
             let lower_id = book.id.to_ascii_lowercase();
-            if query == lower_id {
+            if query == lower_id
+                || query_isbn.is_some() && isbn::to_isbn13(&book.isbn) == query_isbn
+            {
                 results.push((u32::MAX, lower_title, book));
                 continue;
             }
@@ -247,16 +583,10 @@ impl Books {
             }
         }
 
-        let total = results.len();
-
-        let books = results
+        results
             .into_iter()
-            .skip(search.offset)
-            .take(search.limit)
-            .map(|b| b.2.clone())
-            .collect();
-
-        Ok((total, books))
+            .map(|(score, _, b)| (score, b))
+            .collect()
     }
 
     /// Count the number of books in the given category
@@ -269,32 +599,58 @@ impl Books {
         }
     }
 
-    /// Generates a new unique id based on the authors surname and the category.
-    pub fn generate_id(&self, book: &Book) -> Result<String> {
-        let authors = book.authors.trim();
-        let prefix = id_prefix(
-            authors.split_once(',').map_or(authors, |a| a.0),
-            book.category.trim(),
-        );
+    /// Generates a new unique id with the id scheme of the `settings`.
+    ///
+    /// The current id of the book is kept if it already fits the scheme.
+    pub fn generate_id(
+        &self,
+        book: &Book,
+        settings: &Settings,
+        categories: &Categories,
+    ) -> Result<String> {
+        let template = IdTemplate::new(&settings.id_pattern(), book, categories)?;
         let id = book.id.trim();
-        if id.starts_with(&prefix)
-            && id.len() > prefix.len() + 1
-            && &id[prefix.len()..=prefix.len()] == " "
-        {
+        if template.matches(id) {
             return Ok(id.to_string());
         }
+        Ok(template.next(self.data.keys().map(String::as_str)))
+    }
 
-        // query smallest unused id
-        let mut max_id = 0usize;
-        for key in self.data.keys() {
-            if let Some(suffix) = key.strip_prefix(&prefix)
-                && let Ok(id) = suffix.trim().parse()
-            {
-                max_id = max_id.max(id);
-            }
+    /// Returns the new ids of all books for the id scheme of the `settings`.
+    pub fn reid(&self, settings: &Settings, categories: &Categories) -> Result<Vec<IdChange>> {
+        id_scheme::reid(self.data.values(), settings, categories)
+    }
+
+    /// Changes the book ids.
+    ///
+    /// The loans are updated separately with [`super::Database::rename_references`].
+    /// Nothing is changed if the new ids are not unique.
+    pub fn rename(&mut self, changes: &[IdChange]) -> Result<()> {
+        let changes = changes
+            .iter()
+            .map(|c| (c.old.as_str(), c.new.trim()))
+            .collect::<HashMap<_, _>>();
+        let new_id = |id: &str| changes.get(id).copied().unwrap_or(id).to_string();
+
+        if changes.values().any(|id| id.is_empty()) {
+            return Err(Error::Arguments);
         }
-        max_id += 1;
-        Ok(format!("{prefix} {max_id}"))
+        let mut ids = HashSet::new();
+        if !self.data.keys().all(|id| ids.insert(new_id(id))) {
+            return Err(Error::Duplicate);
+        }
+        self.data = std::mem::take(&mut self.data)
+            .into_values()
+            .map(|mut book| {
+                book.id = new_id(&book.id);
+                (book.id.clone(), book)
+            })
+            .collect();
+        for (old, new) in changes {
+            self.touch(old);
+            self.touch(new);
+        }
+        Ok(())
     }
 
     /// Is the user borrowing or reserving by any books
@@ -318,15 +674,21 @@ impl Books {
         }
 
         for book in self.data.values_mut() {
+            let mut changed = false;
             if let Some(borrower) = &mut book.borrower
                 && borrower.user == from
             {
                 borrower.user = to.to_string();
+                changed = true;
             }
             if let Some(reservation) = &mut book.reservation
                 && reservation == from
             {
                 *reservation = to.to_string();
+                changed = true;
+            }
+            if changed {
+                self.changed.insert(book.id.clone());
             }
         }
         Ok(())
@@ -342,54 +704,39 @@ impl Books {
         for book in self.data.values_mut() {
             if book.category == from {
                 book.category = to.to_string();
+                self.changed.insert(book.id.clone());
             }
         }
         Ok(())
     }
 }
 
-fn id_prefix(author: &str, category: &str) -> String {
-    let mut author = author
-        .rsplit_once(' ') // surname
-        .map_or(author, |s| s.1)
-        .nfd() // decompose -> split ÄÖÜ
-        .map(|c| if c == 'ß' { 'S' } else { c })
-        .filter(char::is_ascii_alphabetic)
-        .map(|c| c.to_ascii_uppercase())
-        .take(4)
-        .collect::<String>();
-
-    if author.is_empty() {
-        author = "XXXX".into();
-    }
-
-    let category = if !category.is_empty() {
-        category
-    } else {
-        "XXXX"
-    };
-
-    format!("{category} {author}")
-}
-
 #[cfg(test)]
 mod tests {
     use super::super::*;
     use super::*;
 
     #[test]
-    fn id_prefix() {
-        use super::id_prefix;
-        assert_eq!(id_prefix("Isabel Abedi", "FANT"), "FANT ABED".to_string());
-        assert_eq!(id_prefix("Isabel Äbedi", "FANT"), "FANT ABED".to_string());
-        assert_eq!(id_prefix("", "FANT"), "FANT XXXX".to_string());
-        assert_eq!(id_prefix("äÖü", "FANT"), "FANT AOU".to_string());
-        assert_eq!(id_prefix("äÖüß", "FANT"), "FANT AOUS".to_string());
+    fn parse_author() {
+        let author = Author::parse("J. R. R. Tolkien");
+        assert_eq!(
+            (author.forename.as_str(), author.surname.as_str()),
+            ("J. R. R.", "Tolkien")
+        );
+        let author = Author::parse("Tolkien, J. R. R.");
+        assert_eq!(
+            (author.forename.as_str(), author.surname.as_str()),
+            ("J. R. R.", "Tolkien")
+        );
+        let author = Author::parse("Melissa de la Cruz");
+        assert_eq!(author.surname, "de la Cruz");
+        assert_eq!(author.sort_name(), "Cruz");
+        assert_eq!(author.name(), "Melissa de la Cruz");
+        let author = Author::parse(" Homer ");
         assert_eq!(
-            id_prefix("Remigius Bäumer", "RErk"),
-            "RErk BAUM".to_string()
+            (author.forename.as_str(), author.surname.as_str()),
+            ("", "Homer")
         );
-        assert_eq!(id_prefix("Isabel Abedi", ""), "XXXX ABED".to_string());
     }
 
     #[test]
@@ -426,7 +773,8 @@ mod tests {
             note: "Not a real book".into(),
             borrowable: true,
             category: "FANT".into(),
-            authors: "John Doe".into(),
+            authors: vec!["John Doe".into()],
+            added: NaiveDate::from_ymd_opt(2020, 9, 1),
             ..Book::default()
         };
 
@@ -450,9 +798,11 @@ mod tests {
                 &book.id,
                 Book {
                     title: "Another Title".into(),
+                    added: None, // <- is kept
                     ..book.clone()
                 },
                 &db.categories,
+                &db.users,
             )
             .unwrap();
 
@@ -466,6 +816,7 @@ mod tests {
             .1;
         assert_eq!(1, db_book.len());
         assert_eq!(db_book[0].title, "Another Title");
+        assert_eq!(db_book[0].added, book.added);
 
         // Remove book
         db.books.delete(&book.id).unwrap();
@@ -481,4 +832,154 @@ mod tests {
             0
         );
     }
+
+    #[test]
+    fn search_isbn_forms() {
+        let mut db = Database::default();
+        db.categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: "General".into(),
+            })
+            .unwrap();
+        db.books
+            .add(
+                Book {
+                    id: "FANT TOLK 1".into(),
+                    isbn: "3-608-93977-6".into(),
+                    title: "Der Hobbit".into(),
+                    category: "FANT".into(),
+                    ..Book::default()
+                },
+                &db.categories,
+                &db.users,
+            )
+            .unwrap();
+
+        for query in ["3608939776", "978-3-608-93977-4"] {
+            let (total, books) = db
+                .books
+                .search(&BookSearch {
+                    query: query.into(),
+                    ..BookSearch::default()
+                })
+                .unwrap();
+            assert_eq!(total, 1, "{query}");
+            assert_eq!(books[0].id, "FANT TOLK 1");
+        }
+    }
+
+    #[test]
+    fn duplicates_and_merge() {
+        let mut db = Database::default();
+        db.categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: "General".into(),
+            })
+            .unwrap();
+        let hobbit = Book {
+            id: "FANT TOLK 1".into(),
+            isbn: "3608939776".into(),
+            title: "Der Hobbit".into(),
+            authors: vec!["J. R. R. Tolkien".into()],
+            category: "FANT".into(),
+            borrowable: true,
+            ..Book::default()
+        };
+        for book in [
+            hobbit.clone(),
+            Book {
+                id: "FANT TOLK 2".into(),
+                title: "Der Hobbit".into(),
+                authors: vec!["J.R.R. Tolkien".into()],
+                publisher: "Klett-Cotta".into(),
+                note: "Spende".into(),
+                ..hobbit.clone()
+            },
+            Book {
+                id: "FANT TOLK 3".into(),
+                isbn: "".into(),
+                title: "Die Gefährten".into(),
+                ..hobbit.clone()
+            },
+        ] {
+            db.books.add(book, &db.categories, &db.users).unwrap();
+        }
+
+        let new = Book {
+            id: "".into(),
+            isbn: "978-3-608-93977-4".into(),
+            title: "Hobbit".into(),
+            ..hobbit.clone()
+        };
+        let ids: Vec<_> = db
+            .books
+            .duplicates(&new)
+            .into_iter()
+            .map(|b| b.id)
+            .collect();
+        assert_eq!(ids, ["FANT TOLK 1", "FANT TOLK 2"]);
+        let new = Book {
+            isbn: "".into(),
+            ..hobbit.clone()
+        };
+        let ids: Vec<_> = db
+            .books
+            .duplicates(&new)
+            .into_iter()
+            .map(|b| b.id)
+            .collect();
+        assert_eq!(ids, ["FANT TOLK 2"]);
+
+        // missing fields are not compared
+        let new = Book {
+            id: "FANT TOLK 4".into(),
+            isbn: "".into(),
+            authors: Vec::new(),
+            ..hobbit.clone()
+        };
+        let ids: Vec<_> = db
+            .books
+            .duplicates(&new)
+            .into_iter()
+            .map(|b| b.id)
+            .collect();
+        assert_eq!(ids, ["FANT TOLK 1", "FANT TOLK 2"]);
+        db.books.add(new, &db.categories, &db.users).unwrap();
+        let new = Book {
+            id: "FANT TOLK 5".into(),
+            isbn: "".into(),
+            title: " ".into(),
+            ..hobbit.clone()
+        };
+        assert!(db.books.duplicates(&new).is_empty());
+        assert!(db.books.add(new, &db.categories, &db.users).is_err());
+
+        // the loans are moved along
+        db.users
+            .add(User {
+                account: "foo.bar".into(),
+                forename: "Foo".into(),
+                surname: "Bar".into(),
+                role: "Demo".into(),
+                may_borrow: true,
+            })
+            .unwrap();
+        let deadline = NaiveDate::from_ymd_opt(2030, 1, 1).unwrap();
+        db.lend("FANT TOLK 2", "foo.bar", deadline).unwrap();
+
+        let merged = db.merge_books("FANT TOLK 1", "FANT TOLK 2").unwrap();
+        assert_eq!(merged.publisher, "Klett-Cotta");
+        assert_eq!(merged.note, "Spende");
+        assert_eq!(merged.borrower.unwrap().user, "foo.bar");
+        assert!(db.books.fetch("FANT TOLK 2").is_err());
+        assert!(db.loans.data.iter().all(|l| l.book == "FANT TOLK 1"));
+        assert!(db.check().is_empty());
+        db.return_back("FANT TOLK 1").unwrap();
+        assert!(db.loans.data[0].returned.is_some());
+        assert!(db.merge_books("FANT TOLK 1", "FANT TOLK 1").is_err());
+    }
 }
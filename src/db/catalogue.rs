@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use chrono::NaiveDate;
+use gluer::metadata;
+use serde::{Deserialize, Serialize};
+
+use super::{Author, Book, BookSearch, Condition, Database};
+use crate::isbn;
+
+/// Catalogue of another schiller-lib instance
+#[metadata]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default)]
+pub struct RemoteCatalogue {
+    /// Name shown for its copies
+    pub name: String,
+    /// Base URL of the instance, e.g., `https://library.example.org`
+    pub url: String,
+}
+
+impl RemoteCatalogue {
+    pub(super) fn validate(&mut self) -> bool {
+        self.name = self.name.trim().to_string();
+        self.url = self.url.trim().trim_end_matches('/').to_string();
+        !self.name.is_empty()
+            && (self.url.starts_with("https://") || self.url.starts_with("http://"))
+    }
+}
+
+/// Availability of a copy
+#[metadata]
+#[repr(i64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Availability {
+    /// Can be borrowed
+    Available = 0,
+    /// Is borrowed by someone
+    Borrowed,
+    /// Is reserved by someone
+    Reserved,
+    /// Cannot be borrowed
+    NotBorrowable,
+}
+
+impl Availability {
+    fn of(book: &Book) -> Self {
+        if !book.borrowable {
+            Availability::NotBorrowable
+        } else if book.reservation.is_some() {
+            Availability::Reserved
+        } else if book.borrower.is_some() {
+            Availability::Borrowed
+        } else {
+            Availability::Available
+        }
+    }
+}
+
+/// Copy of a title in one of the libraries
+#[metadata]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Holding {
+    /// Name of the library, empty for this one
+    pub library: String,
+    pub id: String,
+    /// Name of the category
+    pub category: String,
+    pub availability: Availability,
+    /// End of the current loan
+    #[meta(optional, into = String)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<NaiveDate>,
+}
+
+/// Catalogue search result of a single library, without personal data
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CatalogueHit {
+    /// Relevance, comparable between libraries
+    pub score: u32,
+    pub isbn: String,
+    pub title: String,
+    pub authors: Vec<Author>,
+    pub publisher: String,
+    pub year: i64,
+    pub holding: Holding,
+}
+
+/// Title of the union catalogue with its copies in all libraries
+#[metadata]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnionTitle {
+    pub isbn: String,
+    pub title: String,
+    pub authors: Vec<Author>,
+    pub publisher: String,
+    pub year: i64,
+    pub holdings: Vec<Holding>,
+}
+
+impl Database {
+    /// Searches the catalogue on behalf of other libraries.
+    ///
+    /// Lost books and personal data, like borrowers, are left out.
+    pub fn catalogue(&self, query: &str, limit: usize) -> Vec<CatalogueHit> {
+        // do not hand out the whole catalogue
+        if query.trim().is_empty() {
+            return Vec::new();
+        }
+        let search = BookSearch {
+            query: query.into(),
+            ..Default::default()
+        };
+        self.books
+            .ranked(&search)
+            .into_iter()
+            .filter(|(_, book)| book.condition != Condition::Lost)
+            .take(limit)
+            .map(|(score, book)| CatalogueHit {
+                score,
+                isbn: book.isbn.clone(),
+                title: book.title.clone(),
+                authors: book.authors.clone(),
+                publisher: book.publisher.clone(),
+                year: book.year,
+                holding: Holding {
+                    library: String::new(),
+                    id: book.id.clone(),
+                    category: self
+                        .categories
+                        .data
+                        .get(&book.category)
+                        .map_or_else(|| book.category.clone(), |c| c.name.clone()),
+                    availability: Availability::of(book),
+                    deadline: book.borrower.as_ref().map(|b| b.deadline),
+                },
+            })
+            .collect()
+    }
+}
+
+/// Merges the search results of several libraries into titles,
+/// ranked by their best match.
+///
+/// Copies are grouped by their ISBN, or by title and authors if they have none.
+/// The copies of this library come first, then the available ones.
+pub fn union_catalogue(
+    hits: impl IntoIterator<Item = CatalogueHit>,
+    limit: usize,
+) -> Vec<UnionTitle> {
+    let mut titles: Vec<(u32, UnionTitle)> = Vec::new();
+    let mut keys = HashMap::<String, usize>::new();
+    for hit in hits {
+        match keys.entry(title_key(&hit)) {
+            Entry::Occupied(entry) => {
+                let (score, title) = &mut titles[*entry.get()];
+                *score = (*score).max(hit.score);
+                title.holdings.push(hit.holding);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(titles.len());
+                titles.push((
+                    hit.score,
+                    UnionTitle {
+                        isbn: hit.isbn,
+                        title: hit.title,
+                        authors: hit.authors,
+                        publisher: hit.publisher,
+                        year: hit.year,
+                        holdings: vec![hit.holding],
+                    },
+                ));
+            }
+        }
+    }
+
+    titles.sort_by(|a, b| {
+        b.0.cmp(&a.0)
+            .then_with(|| a.1.title.to_lowercase().cmp(&b.1.title.to_lowercase()))
+    });
+    titles.truncate(limit);
+    titles
+        .into_iter()
+        .map(|(_, mut title)| {
+            title.holdings.sort_by(|a, b| {
+                (!a.library.is_empty(), a.availability, &a.library, &a.id).cmp(&(
+                    !b.library.is_empty(),
+                    b.availability,
+                    &b.library,
+                    &b.id,
+                ))
+            });
+            title
+        })
+        .collect()
+}
+
+fn title_key(hit: &CatalogueHit) -> String {
+    if let Some(isbn) = isbn::to_isbn13(&hit.isbn) {
+        return isbn;
+    }
+    let mut key = hit.title.trim().to_lowercase();
+    for author in &hit.authors {
+        key.push('\n');
+        key.push_str(&author.surname.trim().to_lowercase());
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use super::*;
+    use crate::db::{Borrower, Category, User};
+
+    fn library(books: &[(&str, &str, &str)]) -> Database {
+        let mut db = Database::default();
+        db.categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: String::new(),
+            })
+            .unwrap();
+        db.users
+            .add(User {
+                account: "foo.bar".into(),
+                forename: "Foo".into(),
+                surname: "Bar".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        for (id, isbn, title) in books {
+            db.books
+                .add(
+                    Book {
+                        id: id.to_string(),
+                        isbn: isbn.to_string(),
+                        title: title.to_string(),
+                        category: "FANT".into(),
+                        borrowable: true,
+                        authors: vec![Author {
+                            surname: "Funke".into(),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    },
+                    &db.categories,
+                    &db.users,
+                )
+                .unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn merge_libraries() {
+        let mut own = library(&[
+            ("FANT 1", "3-7915-0464-9", "Tintenherz"),
+            ("FANT 2", "", "Tintenblut"),
+        ]);
+        let deadline = Local::now().naive_local().date();
+        own.books.data.get_mut("FANT 1").unwrap().borrower = Some(Borrower {
+            user: "foo.bar".into(),
+            deadline,
+        });
+        let other = library(&[
+            ("F 7", "9783791504643", "Tintenherz"),
+            ("F 8", "", "Tintenblut"),
+            ("F 9", "", "Tintentod"),
+        ]);
+
+        assert!(own.catalogue(" ", 10).is_empty());
+        let hits = own.catalogue("tintenherz", 10);
+        assert_eq!(hits.len(), 1);
+        let holding = &hits[0].holding;
+        assert_eq!(holding.category, "Fantasy");
+        assert_eq!(holding.availability, Availability::Borrowed);
+        assert_eq!(holding.deadline, Some(deadline));
+
+        let query = "tinten";
+        let hits =
+            own.catalogue(query, 10)
+                .into_iter()
+                .chain(other.catalogue(query, 10).into_iter().map(|mut hit| {
+                    hit.holding.library = "Other".into();
+                    hit
+                }));
+        let titles = union_catalogue(hits, 10);
+        let copies = titles
+            .iter()
+            .map(|t| {
+                let ids = t.holdings.iter().map(|h| h.id.as_str());
+                (t.title.as_str(), ids.collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            copies,
+            [
+                ("Tintenblut", vec!["FANT 2", "F 8"]),
+                ("Tintenherz", vec!["FANT 1", "F 7"]),
+                ("Tintentod", vec!["F 9"]),
+            ]
+        );
+
+        assert_eq!(union_catalogue(own.catalogue(query, 10), 1).len(), 1);
+    }
+}
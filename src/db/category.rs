@@ -1,5 +1,5 @@
-use std::collections::BTreeMap;
 use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
 
 use gluer::metadata;
 use serde::{Deserialize, Serialize};
@@ -26,13 +26,26 @@ impl Category {
     }
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Categories {
+    /// Direct modifications have to be recorded with [`Categories::touch`]
     #[serde(flatten)]
     pub data: BTreeMap<String, Category>,
+    /// Ids of the modified categories, that are not yet saved
+    #[serde(skip)]
+    changed: BTreeSet<String>,
 }
 
 impl Categories {
+    /// Record the modification of the category, to be saved with the next commit.
+    pub fn touch(&mut self, id: &str) {
+        self.changed.insert(id.into());
+    }
+    /// Returns and resets the ids of the modified categories.
+    pub(super) fn take_changed(&mut self) -> BTreeSet<String> {
+        std::mem::take(&mut self.changed)
+    }
+
     pub fn list(&self) -> Result<Vec<Category>> {
         Ok(self.data.values().cloned().collect())
     }
@@ -45,6 +58,7 @@ impl Categories {
         match self.data.entry(category.id.clone()) {
             Entry::Vacant(v) => {
                 v.insert(category.clone());
+                self.touch(&category.id);
                 Ok(category)
             }
             _ => Err(Error::Duplicate),
@@ -66,14 +80,17 @@ impl Categories {
         if id == category.id {
             if let Some(entry) = self.data.get_mut(id) {
                 *entry = category.clone();
+                self.touch(id);
                 return Ok(category);
             }
         } else if self.data.contains_key(id) {
             return match self.data.entry(category.id.clone()) {
                 Entry::Vacant(v) => {
                     v.insert(category.clone());
+                    self.touch(&category.id);
                     books.update_category(id, &category.id)?;
                     self.data.remove(id);
+                    self.touch(id);
                     Ok(category)
                 }
                 _ => Err(Error::Duplicate),
@@ -95,6 +112,8 @@ impl Categories {
                 return Err(Error::ReferencedCategory);
             }
         }
-        self.data.remove(id).map(|_| ()).ok_or(Error::NothingFound)
+        self.data.remove(id).ok_or(Error::NothingFound)?;
+        self.touch(id);
+        Ok(())
     }
 }
@@ -0,0 +1,397 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use gluer::metadata;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+use super::{Author, Book, Categories, Settings};
+use crate::error::{Error, Result};
+
+/// Pattern of the default scheme
+const CATEGORY_PATTERN: &str = "{category} {author} {n}";
+/// Replacement for missing placeholder values
+const UNKNOWN: &str = "XXXX";
+
+/// Scheme for generating new book ids
+#[metadata]
+#[repr(i64)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IdScheme {
+    /// "<category> <author> <n>", e.g., "FANT TOLK 1"
+    #[default]
+    Category = 0,
+    /// Number with a fixed prefix, e.g., for numeric barcodes
+    Sequential,
+    /// Custom pattern with placeholders
+    Pattern,
+}
+
+/// Book id that was changed by a re-id.
+#[metadata]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdChange {
+    pub old: String,
+    pub new: String,
+}
+
+impl Settings {
+    /// Pattern for new book ids.
+    ///
+    /// Supported placeholders are `{category}`, `{section}`, `{author}`,
+    /// `{year}`, and exactly one counter `{n}`, optionally zero-padded with
+    /// `{n:<digits>}`.
+    pub fn id_pattern(&self) -> String {
+        match self.id_scheme {
+            IdScheme::Category => CATEGORY_PATTERN.into(),
+            IdScheme::Sequential => format!("{}{{n:{}}}", self.id_prefix, self.id_digits),
+            IdScheme::Pattern => self.id_pattern.clone(),
+        }
+    }
+}
+
+/// Pattern with the placeholders of a book replaced, except the counter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IdTemplate {
+    prefix: String,
+    /// Minimal number of digits of the counter
+    width: usize,
+    suffix: String,
+}
+
+impl IdTemplate {
+    /// Replaces the placeholders of the `pattern` with the values of the book.
+    pub fn new(pattern: &str, book: &Book, categories: &Categories) -> Result<Self> {
+        let category = book.category.trim();
+        Self::parse(pattern, |placeholder| {
+            Some(match placeholder {
+                "category" if category.is_empty() => UNKNOWN.into(),
+                "category" => category.into(),
+                "section" => abbreviate(categories.data.get(category).map_or("", |c| &c.section)),
+                "author" => abbreviate(book.authors.first().map_or("", Author::sort_name)),
+                "year" => book.year.to_string(),
+                _ => return None,
+            })
+        })
+    }
+
+    /// Checks if the pattern is valid.
+    pub fn validate(pattern: &str) -> bool {
+        let known = ["category", "section", "author", "year"];
+        Self::parse(pattern, |p| known.contains(&p).then(String::new)).is_ok()
+    }
+
+    fn parse(pattern: &str, value: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut prefix = String::new();
+        let mut width = None;
+        let mut suffix = String::new();
+        let mut rest = pattern;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}').ok_or(Error::InvalidFormat)? + start;
+            let out = if width.is_some() {
+                &mut suffix
+            } else {
+                &mut prefix
+            };
+            out.push_str(&rest[..start]);
+            let placeholder = &rest[start + 1..end];
+            if let Some(value) = value(placeholder) {
+                out.push_str(&value);
+            } else if width.is_none() && placeholder == "n" {
+                width = Some(1);
+            } else if let Some(digits) = placeholder.strip_prefix("n:")
+                && width.is_none()
+            {
+                width = Some(digits.parse().map_err(|_| Error::InvalidFormat)?);
+            } else {
+                return Err(Error::InvalidFormat);
+            }
+            rest = &rest[end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(Error::InvalidFormat);
+        }
+        let width = width.ok_or(Error::InvalidFormat)?;
+        suffix.push_str(rest);
+        Ok(Self {
+            prefix,
+            width,
+            suffix,
+        })
+    }
+
+    /// Is the id generated from this template.
+    pub fn matches(&self, id: &str) -> bool {
+        self.counter(id).is_some()
+    }
+
+    /// Returns the id with the smallest unused counter.
+    pub fn next<'a>(&self, ids: impl IntoIterator<Item = &'a str>) -> String {
+        let max_id = ids.into_iter().filter_map(|id| self.counter(id)).max();
+        self.format(max_id.unwrap_or(0) + 1)
+    }
+
+    /// Counter of an id generated from this template.
+    fn counter(&self, id: &str) -> Option<usize> {
+        let counter = id.strip_prefix(&self.prefix)?.strip_suffix(&self.suffix)?;
+        counter.trim().parse().ok()
+    }
+
+    fn format(&self, counter: usize) -> String {
+        let (prefix, width, suffix) = (&self.prefix, self.width, &self.suffix);
+        format!("{prefix}{counter:0width$}{suffix}")
+    }
+}
+
+/// Assigns new ids to the books whose ids do not fit the scheme,
+/// numbered after the existing ids of the same template.
+///
+/// Ids that already fit are kept, as they might be printed on labels.
+pub fn reid<'a>(
+    books: impl IntoIterator<Item = &'a Book>,
+    settings: &Settings,
+    categories: &Categories,
+) -> Result<Vec<IdChange>> {
+    let pattern = settings.id_pattern();
+    // highest counter of the kept ids per template
+    let mut counters = HashMap::new();
+    let mut unfit = Vec::new();
+    for book in books {
+        let template = IdTemplate::new(&pattern, book, categories)?;
+        let counter = template.counter(&book.id);
+        let max = counters.entry(template.clone()).or_insert(0);
+        if let Some(counter) = counter {
+            *max = counter.max(*max);
+        } else {
+            unfit.push((book, template));
+        }
+    }
+
+    let mut changes = Vec::new();
+    for (book, template) in unfit {
+        let counter = counters.get_mut(&template).unwrap();
+        *counter += 1;
+        changes.push(IdChange {
+            old: book.id.clone(),
+            new: template.format(*counter),
+        });
+    }
+    Ok(changes)
+}
+
+/// Compares ids in natural order, e.g., "FANT 2" before "FANT 10".
+pub fn cmp_ids(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let end_a = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let end_b = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let n = a[..end_a].trim_start_matches('0');
+            let m = b[..end_b].trim_start_matches('0');
+            let ordering = n.len().cmp(&m.len()).then_with(|| n.cmp(m));
+            if ordering.is_ne() {
+                return ordering;
+            }
+            (a, b) = (&a[end_a..], &b[end_b..]);
+        } else if x != y {
+            return x.cmp(&y);
+        } else {
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        }
+    }
+}
+
+/// Uppercase the first four ascii letters, e.g., "Bäumer" -> "BAUM"
+fn abbreviate(name: &str) -> String {
+    let name = name
+        .nfd() // decompose -> split ÄÖÜ
+        .map(|c| if c == 'ß' { 'S' } else { c })
+        .filter(char::is_ascii_alphabetic)
+        .map(|c| c.to_ascii_uppercase())
+        .take(4)
+        .collect::<String>();
+    if name.is_empty() {
+        UNKNOWN.into()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Category, Database};
+
+    #[test]
+    fn abbreviations() {
+        assert_eq!(abbreviate("Abedi"), "ABED");
+        assert_eq!(abbreviate("Äbedi"), "ABED");
+        assert_eq!(abbreviate(""), "XXXX");
+        assert_eq!(abbreviate("äÖü"), "AOU");
+        assert_eq!(abbreviate("äÖüß"), "AOUS");
+        assert_eq!(abbreviate("Bäumer"), "BAUM");
+    }
+
+    #[test]
+    fn natural_order() {
+        assert_eq!(cmp_ids("FANT 2", "FANT 10"), Ordering::Less);
+        assert_eq!(cmp_ids("FANT 10", "FANT 9"), Ordering::Greater);
+        assert_eq!(cmp_ids("FANT 9", "FANT ABED 1"), Ordering::Less);
+        assert_eq!(cmp_ids("FANT TOLK 2", "FANT TOLK 2"), Ordering::Equal);
+        assert_eq!(cmp_ids("FANT", "FANT 1"), Ordering::Less);
+        assert_eq!(cmp_ids("000010", "000009"), Ordering::Greater);
+    }
+
+    #[test]
+    fn templates() {
+        let mut categories = Categories::default();
+        categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: "Belletristik".into(),
+            })
+            .unwrap();
+        let book = Book {
+            category: "FANT".into(),
+            authors: vec!["Isabel Abedi".into()],
+            year: 2006,
+            ..Book::default()
+        };
+        let template = |pattern: &str| IdTemplate::new(pattern, &book, &categories);
+
+        let default = template(CATEGORY_PATTERN).unwrap();
+        assert_eq!(
+            default.next(["FANT ABED 1", "FANT ABED 3", "FANT BAUM 5"]),
+            "FANT ABED 4"
+        );
+        assert!(default.matches("FANT ABED 2"));
+        assert!(!default.matches("FANT ABED "));
+        assert!(!default.matches("FANT ABED X"));
+
+        let sequential = template("{n:6}").unwrap();
+        assert_eq!(sequential.next(["000041", "FANT ABED 1"]), "000042");
+        assert!(sequential.matches("000041"));
+        assert!(!sequential.matches("FANT ABED 1"));
+
+        let custom = template("{section}/{year}-{n:2}").unwrap();
+        assert_eq!(custom.next([]), "BELL/2006-01");
+        let custom = template("{n}-{category}").unwrap();
+        assert_eq!(custom.next(["1-FANT"]), "2-FANT");
+
+        for invalid in [
+            "",
+            "{category}",
+            "{n}{n}",
+            "{n",
+            "n}",
+            "{n:x}",
+            "{unknown} {n}",
+        ] {
+            assert!(template(invalid).is_err(), "{invalid}");
+            assert!(!IdTemplate::validate(invalid), "{invalid}");
+        }
+        assert!(IdTemplate::validate("{section}-{author}-{n:4}"));
+    }
+
+    #[test]
+    fn reid_books() {
+        let mut db = Database::default();
+        db.categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: "Belletristik".into(),
+            })
+            .unwrap();
+        for (id, title) in [
+            ("FANT TOLK 1", "Der Hobbit"),
+            ("FANT TOLK 2", "Die Gefährten"),
+        ] {
+            let book = Book {
+                id: id.into(),
+                title: title.into(),
+                category: "FANT".into(),
+                ..Book::default()
+            };
+            db.books.add(book, &db.categories, &db.users).unwrap();
+        }
+
+        let settings = Settings {
+            id_scheme: IdScheme::Sequential,
+            id_prefix: "B".into(),
+            id_digits: 3,
+            ..Settings::default()
+        };
+        let changes = db.books.reid(&settings, &db.categories).unwrap();
+        assert_eq!(
+            changes,
+            [
+                IdChange {
+                    old: "FANT TOLK 1".into(),
+                    new: "B001".into()
+                },
+                IdChange {
+                    old: "FANT TOLK 2".into(),
+                    new: "B002".into()
+                }
+            ]
+        );
+        db.books.rename(&changes).unwrap();
+        assert_eq!(db.books.fetch("B002").unwrap().title, "Die Gefährten");
+        assert!(db.books.fetch("FANT TOLK 1").is_err());
+
+        let conflict = IdChange {
+            old: "B001".into(),
+            new: "B002".into(),
+        };
+        assert!(matches!(
+            db.books.rename(&[conflict]),
+            Err(Error::Duplicate)
+        ));
+        assert_eq!(db.books.fetch("B001").unwrap().title, "Der Hobbit");
+    }
+
+    #[test]
+    fn reid_keeps_fitting_ids() {
+        let mut db = Database::default();
+        db.categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: "Belletristik".into(),
+            })
+            .unwrap();
+        for id in ["FANT TOLK 10", "FANT TOLK 2"] {
+            let book = Book {
+                id: id.into(),
+                title: id.into(),
+                category: "FANT".into(),
+                authors: vec!["J. R. R. Tolkien".into()],
+                ..Book::default()
+            };
+            db.books.add(book, &db.categories, &db.users).unwrap();
+        }
+        let settings = Settings::default();
+        assert_eq!(db.books.reid(&settings, &db.categories).unwrap(), []);
+
+        let book = Book {
+            id: "FANT 7".into(),
+            title: "Das Silmarillion".into(),
+            category: "FANT".into(),
+            authors: vec!["J. R. R. Tolkien".into()],
+            ..Book::default()
+        };
+        db.books.add(book, &db.categories, &db.users).unwrap();
+        assert_eq!(
+            db.books.reid(&settings, &db.categories).unwrap(),
+            [IdChange {
+                old: "FANT 7".into(),
+                new: "FANT TOLK 11".into()
+            }]
+        );
+    }
+}
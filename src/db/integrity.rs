@@ -0,0 +1,357 @@
+use std::fmt;
+
+use chrono::Local;
+use serde::Serialize;
+
+use super::{Category, Database};
+use crate::isbn;
+
+/// Record with a violated invariant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum Entity {
+    Book(String),
+    User(String),
+    Category(String),
+}
+
+impl fmt::Display for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entity::Book(id) => write!(f, "Book {id:?}"),
+            Entity::User(account) => write!(f, "User {account:?}"),
+            Entity::Category(id) => write!(f, "Category {id:?}"),
+        }
+    }
+}
+
+/// Violated invariant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum Problem {
+    /// Stored under a different key than its id
+    Key(String),
+    /// Missing id or title
+    InvalidBook,
+    /// Invalid account or missing names
+    InvalidUser,
+    /// Missing id
+    InvalidCategory,
+    UnknownCategory(String),
+    UnknownBorrower(String),
+    UnknownReservation(String),
+    /// Reserved by the user who borrows it
+    ReservedByBorrower,
+    InvalidIsbn(String),
+}
+
+/// Integrity problem of a record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Issue {
+    pub entity: Entity,
+    pub problem: Problem,
+}
+
+impl Issue {
+    /// Whether the problem can be repaired automatically.
+    pub fn repairable(&self) -> bool {
+        !matches!(
+            self.problem,
+            Problem::InvalidBook
+                | Problem::InvalidUser
+                | Problem::InvalidCategory
+                | Problem::InvalidIsbn(_)
+        ) && self.problem != Problem::UnknownCategory(String::new())
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.entity)?;
+        match &self.problem {
+            Problem::Key(key) => write!(f, "stored as {key:?}, move it to its id"),
+            Problem::InvalidBook => write!(f, "missing id or title, edit or delete it"),
+            Problem::InvalidUser => write!(f, "invalid account or names, edit or delete it"),
+            Problem::InvalidCategory => write!(f, "missing id, edit or delete it"),
+            Problem::UnknownCategory(id) => {
+                write!(f, "unknown category {id:?}, create the category")
+            }
+            Problem::UnknownBorrower(account) => {
+                write!(f, "borrowed by unknown user {account:?}, return the book")
+            }
+            Problem::UnknownReservation(account) => {
+                write!(
+                    f,
+                    "reserved by unknown user {account:?}, release the reservation"
+                )
+            }
+            Problem::ReservedByBorrower => {
+                write!(f, "reserved by its borrower, release the reservation")
+            }
+            Problem::InvalidIsbn(value) => match isbn::repair(value) {
+                Some(repaired) => write!(f, "invalid isbn {value:?}, maybe {repaired:?}"),
+                None => write!(f, "invalid isbn {value:?}, correct or clear it"),
+            },
+        }
+    }
+}
+
+impl Database {
+    /// Validates all books, users, and categories, including their references.
+    pub fn check(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+
+        for (key, category) in &self.categories.data {
+            let entity = || Entity::Category(key.clone());
+            if category.id.trim().is_empty() {
+                issues.push(Issue {
+                    entity: entity(),
+                    problem: Problem::InvalidCategory,
+                });
+            } else if *key != category.id {
+                issues.push(Issue {
+                    entity: entity(),
+                    problem: Problem::Key(key.clone()),
+                });
+            }
+        }
+
+        for (key, user) in &self.users.data {
+            let entity = || Entity::User(key.clone());
+            if !user.clone().validate() {
+                issues.push(Issue {
+                    entity: entity(),
+                    problem: Problem::InvalidUser,
+                });
+            } else if *key != user.account {
+                issues.push(Issue {
+                    entity: entity(),
+                    problem: Problem::Key(key.clone()),
+                });
+            }
+        }
+
+        for (key, book) in &self.books.data {
+            let entity = || Entity::Book(key.clone());
+            let mut problems = Vec::new();
+            if !book.clone().validate() {
+                problems.push(Problem::InvalidBook);
+            } else if *key != book.id {
+                problems.push(Problem::Key(key.clone()));
+            }
+            if !self.categories.data.contains_key(&book.category) {
+                problems.push(Problem::UnknownCategory(book.category.clone()));
+            }
+            if let Some(borrower) = &book.borrower
+                && !self.users.data.contains_key(&borrower.user)
+            {
+                problems.push(Problem::UnknownBorrower(borrower.user.clone()));
+            }
+            if let Some(reservation) = &book.reservation {
+                if !self.users.data.contains_key(reservation) {
+                    problems.push(Problem::UnknownReservation(reservation.clone()));
+                } else if book
+                    .borrower
+                    .as_ref()
+                    .is_some_and(|b| b.user == *reservation)
+                {
+                    problems.push(Problem::ReservedByBorrower);
+                }
+            }
+            if !book.isbn.is_empty() && isbn::parse(&book.isbn).is_err() {
+                problems.push(Problem::InvalidIsbn(book.isbn.clone()));
+            }
+            issues.extend(problems.into_iter().map(|problem| Issue {
+                entity: entity(),
+                problem,
+            }));
+        }
+        issues
+    }
+
+    /// Repairs the problems that can be fixed automatically.
+    ///
+    /// Returns the repaired issues.
+    pub fn repair(&mut self) -> Vec<Issue> {
+        let mut repaired = Vec::new();
+        for issue in self.check() {
+            if issue.repairable() && self.repair_issue(&issue) {
+                repaired.push(issue);
+            }
+        }
+        repaired
+    }
+
+    fn repair_issue(&mut self, Issue { entity, problem }: &Issue) -> bool {
+        match (entity, problem) {
+            (Entity::Category(key), Problem::Key(_)) => {
+                let category = &self.categories.data[key];
+                if self.categories.data.contains_key(&category.id) {
+                    return false;
+                }
+                let category = self.categories.data.remove(key).unwrap();
+                self.categories.touch(key);
+                self.categories.touch(&category.id);
+                self.categories.data.insert(category.id.clone(), category);
+            }
+            (Entity::User(key), Problem::Key(_)) => {
+                let user = &self.users.data[key];
+                if self.users.data.contains_key(&user.account) {
+                    return false;
+                }
+                let user = self.users.data.remove(key).unwrap();
+                self.users.touch(key);
+                self.users.touch(&user.account);
+                self.users.data.insert(user.account.clone(), user);
+            }
+            (Entity::Book(key), Problem::Key(_)) => {
+                let book = &self.books.data[key];
+                if self.books.data.contains_key(&book.id) {
+                    return false;
+                }
+                let book = self.books.data.remove(key).unwrap();
+                self.books.touch(key);
+                self.books.touch(&book.id);
+                self.books.data.insert(book.id.clone(), book);
+            }
+            (_, Problem::UnknownCategory(id)) => {
+                // might already be created for another book
+                if !self.categories.data.contains_key(id) {
+                    let category = Category {
+                        id: id.clone(),
+                        name: id.clone(),
+                        section: String::new(),
+                    };
+                    if self.categories.add(category).is_err() {
+                        return false;
+                    }
+                }
+            }
+            (Entity::Book(key), Problem::UnknownBorrower(_)) => {
+                let Some(book) = self.books.data.get_mut(key) else {
+                    return false;
+                };
+                book.borrower = None;
+                self.books.touch(key);
+                // close the loan, so that the book can be lent again
+                self.loans
+                    .return_back(key, Local::now().naive_local().date());
+            }
+            (Entity::Book(key), Problem::UnknownReservation(_) | Problem::ReservedByBorrower) => {
+                let Some(book) = self.books.data.get_mut(key) else {
+                    return false;
+                };
+                book.reservation = None;
+                self.books.touch(key);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Book, Borrower, User};
+
+    #[test]
+    fn check_and_repair() {
+        let mut db = Database::default();
+        db.categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: String::new(),
+            })
+            .unwrap();
+        db.users
+            .add(User {
+                account: "foo.bar".into(),
+                forename: "Foo".into(),
+                surname: "Bar".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        let book = |id: &str| Book {
+            id: id.into(),
+            title: id.into(),
+            category: "FANT".into(),
+            ..Default::default()
+        };
+        db.books
+            .add(book("FANT 1"), &db.categories, &db.users)
+            .unwrap();
+        assert!(db.check().is_empty());
+
+        // as in hand edited files
+        let books = &mut db.books.data;
+        books.insert(
+            "FANT 2".into(),
+            Book {
+                category: "SACH".into(),
+                isbn: "3-570-12893-1".into(),
+                borrower: Some(Borrower {
+                    user: "nobody".into(),
+                    deadline: Default::default(),
+                }),
+                ..book("FANT 2")
+            },
+        );
+        books.insert(
+            "FANT 3".into(),
+            Book {
+                borrower: Some(Borrower {
+                    user: "foo.bar".into(),
+                    deadline: Default::default(),
+                }),
+                reservation: Some("foo.bar".into()),
+                ..book("FANT 3")
+            },
+        );
+        books.insert("fant 4".into(), book("FANT 4"));
+        books.insert("FANT 5".into(), Book::default());
+        let nobody = User {
+            account: "nobody".into(),
+            ..Default::default()
+        };
+        let lent = Default::default();
+        db.loans.lend(&books["FANT 2"], &nobody, lent, lent);
+
+        let issues = db.check();
+        let problems = issues
+            .iter()
+            .map(|i| (i.entity.clone(), i.problem.clone()))
+            .collect::<Vec<_>>();
+        let book = |id: &str| Entity::Book(id.into());
+        assert_eq!(
+            problems,
+            [
+                (book("FANT 2"), Problem::UnknownCategory("SACH".into())),
+                (book("FANT 2"), Problem::UnknownBorrower("nobody".into())),
+                (book("FANT 2"), Problem::InvalidIsbn("3-570-12893-1".into())),
+                (book("FANT 3"), Problem::ReservedByBorrower),
+                (book("FANT 5"), Problem::InvalidBook),
+                (book("FANT 5"), Problem::UnknownCategory("".into())),
+                (book("fant 4"), Problem::Key("fant 4".into())),
+            ]
+        );
+        assert_eq!(
+            issues[2].to_string(),
+            "Book \"FANT 2\": invalid isbn \"3-570-12893-1\", maybe \"3570128938\""
+        );
+        db.changes();
+
+        let repaired = db.repair();
+        assert_eq!(repaired.len(), 4);
+        assert!(db.categories.data.contains_key("SACH"));
+        assert!(db.books.data["FANT 2"].borrower.is_none());
+        assert!(db.loans.data.iter().all(|l| l.returned.is_some()));
+        assert!(db.books.data["FANT 3"].reservation.is_none());
+        assert!(db.books.data.contains_key("FANT 4"));
+        // the changes, including the closed loan, are saved
+        assert_eq!(db.changes().len(), 6);
+
+        let remaining = db.check();
+        assert_eq!(remaining.len(), 3);
+        assert!(remaining.iter().all(|i| !i.repairable()));
+    }
+}
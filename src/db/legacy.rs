@@ -265,7 +265,12 @@ impl From<Book> for super::Book {
             note: value.note,
             borrowable: value.borrowable,
             category: value.category,
-            authors: value.authors.join(", "),
+            authors: value
+                .authors
+                .iter()
+                .filter(|a| !a.trim().is_empty())
+                .map(|a| super::Author::parse(a))
+                .collect(),
             borrower: if value.deadline.is_empty() {
                 None
             } else {
@@ -275,6 +280,15 @@ impl From<Book> for super::Book {
                 })
             },
             reservation: (!value.reservation.is_empty()).then_some(value.reservation),
+            edition: String::new(),
+            language: String::new(),
+            pages: 0,
+            series: String::new(),
+            subjects: Vec::new(),
+            ddc: String::new(),
+            cover: String::new(),
+            added: None,
+            condition: super::Condition::Good,
         }
     }
 }
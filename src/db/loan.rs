@@ -0,0 +1,395 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::{Months, NaiveDate};
+use gluer::metadata;
+use serde::{Deserialize, Serialize};
+
+use super::{Book, Books, Categories, IdChange, User};
+
+/// Past or current loan of a book.
+///
+/// The title, category, and role are stored at the time of lending,
+/// so that the statistics are not affected by later changes.
+/// The account of the borrower is not stored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Loan {
+    pub book: String,
+    pub title: String,
+    pub category: String,
+    /// Role of the borrower
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub role: String,
+    pub lent: NaiveDate,
+    pub deadline: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub returned: Option<NaiveDate>,
+}
+
+impl Loan {
+    /// Is, or was, the book returned after the deadline.
+    fn is_overdue(&self, today: NaiveDate) -> bool {
+        self.returned.unwrap_or(today) > self.deadline
+    }
+}
+
+/// Grouping of the loan statistics
+#[metadata]
+#[repr(i64)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LoanGroup {
+    /// Month of lending, e.g., "2024-09"
+    #[default]
+    Month = 0,
+    Category,
+    Section,
+    /// Role of the borrower
+    Role,
+    /// Book, ordered by the number of loans
+    Title,
+}
+
+/// Number of loans for a month, category, section, role, or book
+#[metadata]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoanCount {
+    pub key: String,
+    /// Category or book title
+    #[meta(optional)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub loans: usize,
+}
+
+/// Overall loan statistics of a period
+#[metadata]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoanSummary {
+    pub loans: usize,
+    pub returned: usize,
+    /// Average duration of the returned loans in days
+    pub average_days: f64,
+    /// Loans returned after or still open past their deadline
+    pub overdue: usize,
+    /// Ratio of overdue loans (0 to 1)
+    pub overdue_rate: f64,
+}
+
+/// Inclusive period, open ended if a bound is missing
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Period {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl Period {
+    fn contains(&self, date: NaiveDate) -> bool {
+        self.from.is_none_or(|from| from <= date) && self.to.is_none_or(|to| date <= to)
+    }
+}
+
+/// History of all loans, in the order of lending
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Loans {
+    pub data: Vec<Loan>,
+    /// Indices of the modified loans, that are not yet saved
+    #[serde(skip)]
+    changed: BTreeSet<usize>,
+}
+
+impl Loans {
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns and resets the indices of the modified loans.
+    pub(super) fn take_changed(&mut self) -> BTreeSet<usize> {
+        std::mem::take(&mut self.changed)
+    }
+    /// Record the modification of the loan, to be saved with the next commit.
+    pub(super) fn touch(&mut self, index: usize) {
+        self.changed.insert(index);
+    }
+
+    /// Index of the open loan of the book
+    fn open(&self, book: &str) -> Option<usize> {
+        self.data
+            .iter()
+            .rposition(|l| l.book == book && l.returned.is_none())
+    }
+
+    /// Records a new loan or extends the deadline of the open one.
+    pub fn lend(&mut self, book: &Book, user: &User, lent: NaiveDate, deadline: NaiveDate) {
+        if let Some(i) = self.open(&book.id) {
+            self.data[i].deadline = deadline;
+            self.changed.insert(i);
+        } else {
+            self.changed.insert(self.data.len());
+            self.data.push(Loan {
+                book: book.id.clone(),
+                title: book.title.clone(),
+                category: book.category.clone(),
+                role: user.role.clone(),
+                lent,
+                deadline,
+                returned: None,
+            });
+        }
+    }
+
+    /// Closes the open loan of the book.
+    pub fn return_back(&mut self, book: &str, returned: NaiveDate) {
+        if let Some(i) = self.open(book) {
+            self.data[i].returned = Some(returned);
+            self.changed.insert(i);
+        }
+    }
+
+    /// Updates the book ids of the loans, all changes are applied at once.
+    pub fn rename(&mut self, changes: &[IdChange]) {
+        let changes = changes
+            .iter()
+            .map(|c| (c.old.as_str(), c.new.as_str()))
+            .collect::<HashMap<_, _>>();
+        for (i, loan) in self.data.iter_mut().enumerate() {
+            if let Some(new) = changes.get(loan.book.as_str()) {
+                loan.book = (*new).into();
+                self.changed.insert(i);
+            }
+        }
+    }
+
+    /// Returns the date of the latest loan of each book.
+    pub fn last_lent(&self) -> HashMap<&str, NaiveDate> {
+        let mut last = HashMap::new();
+        for loan in &self.data {
+            let date = last.entry(loan.book.as_str()).or_insert(loan.lent);
+            *date = loan.lent.max(*date);
+        }
+        last
+    }
+
+    fn within(&self, period: Period) -> impl Iterator<Item = &Loan> {
+        self.data.iter().filter(move |l| period.contains(l.lent))
+    }
+
+    /// Counts the loans of the period per group.
+    ///
+    /// Titles are ordered by their number of loans, all others by their key.
+    pub fn count(
+        &self,
+        group: LoanGroup,
+        period: Period,
+        books: &Books,
+        categories: &Categories,
+    ) -> Vec<LoanCount> {
+        let mut counts = BTreeMap::<String, (String, usize)>::new();
+        for loan in self.within(period) {
+            let (key, name) = match group {
+                LoanGroup::Month => (loan.lent.format("%Y-%m").to_string(), String::new()),
+                LoanGroup::Category => {
+                    let name = categories.data.get(&loan.category).map(|c| c.name.clone());
+                    (loan.category.clone(), name.unwrap_or_default())
+                }
+                LoanGroup::Section => {
+                    let section = categories
+                        .data
+                        .get(&loan.category)
+                        .map(|c| c.section.clone());
+                    (section.unwrap_or_default(), String::new())
+                }
+                LoanGroup::Role => (loan.role.clone(), String::new()),
+                LoanGroup::Title => {
+                    let title = books.data.get(&loan.book).map(|b| b.title.clone());
+                    (
+                        loan.book.clone(),
+                        title.unwrap_or_else(|| loan.title.clone()),
+                    )
+                }
+            };
+            counts.entry(key).or_insert((name, 0)).1 += 1;
+        }
+        let mut counts = counts
+            .into_iter()
+            .map(|(key, (name, loans))| LoanCount { key, name, loans })
+            .collect::<Vec<_>>();
+        if group == LoanGroup::Title {
+            counts.sort_by(|a, b| b.loans.cmp(&a.loans).then_with(|| a.key.cmp(&b.key)));
+        }
+        counts
+    }
+
+    /// Returns the duration and overdue statistics of the period.
+    pub fn summary(&self, period: Period, today: NaiveDate) -> LoanSummary {
+        let mut loans = 0;
+        let mut returned = 0;
+        let mut days = 0;
+        let mut overdue = 0;
+        for loan in self.within(period) {
+            loans += 1;
+            if let Some(date) = loan.returned {
+                returned += 1;
+                days += (date - loan.lent).num_days();
+            }
+            if loan.is_overdue(today) {
+                overdue += 1;
+            }
+        }
+        LoanSummary {
+            loans,
+            returned,
+            average_days: if returned > 0 {
+                days as f64 / returned as f64
+            } else {
+                0.0
+            },
+            overdue,
+            overdue_rate: if loans > 0 {
+                overdue as f64 / loans as f64
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// Returns the books that were not lent in the last `years`.
+    ///
+    /// Books added within this time are excluded.
+    pub fn never_borrowed(&self, years: u32, books: &Books, today: NaiveDate) -> Vec<Book> {
+        let since = today
+            .checked_sub_months(Months::new(years.saturating_mul(12)))
+            .unwrap_or(NaiveDate::MIN);
+        let last_lent = self.last_lent();
+        let mut results = Vec::new();
+        for book in books.data.values() {
+            if book.added.is_some_and(|added| added > since)
+                || book.borrower.is_some()
+                || last_lent
+                    .get(book.id.as_str())
+                    .is_some_and(|lent| *lent >= since)
+            {
+                continue;
+            }
+            results.push(book.clone());
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Category, Database};
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn loan_history() {
+        let mut db = Database::default();
+        for (id, section) in [("FANT", "Belletristik"), ("SACH", "Sachbuch")] {
+            db.categories
+                .add(Category {
+                    id: id.into(),
+                    name: id.into(),
+                    section: section.into(),
+                })
+                .unwrap();
+        }
+        for (id, category) in [("FANT 1", "FANT"), ("FANT 2", "FANT"), ("SACH 1", "SACH")] {
+            let book = Book {
+                id: id.into(),
+                title: id.into(),
+                category: category.into(),
+                borrowable: true,
+                added: Some(date(2020, 1, 1)),
+                ..Book::default()
+            };
+            db.books.add(book, &db.categories, &db.users).unwrap();
+        }
+        let student = User {
+            role: "5a".into(),
+            ..User::default()
+        };
+        let teacher = User {
+            role: "Lehrer".into(),
+            ..User::default()
+        };
+        let fant1 = db.books.fetch("FANT 1").unwrap();
+        let sach1 = db.books.fetch("SACH 1").unwrap();
+
+        let mut loans = Loans::default();
+        loans.lend(&fant1, &student, date(2024, 1, 10), date(2024, 2, 10));
+        // renewal
+        loans.lend(&fant1, &student, date(2024, 2, 1), date(2024, 3, 1));
+        loans.return_back("FANT 1", date(2024, 2, 20));
+        loans.lend(&fant1, &teacher, date(2024, 3, 5), date(2024, 4, 5));
+        loans.return_back("FANT 1", date(2024, 4, 15));
+        loans.lend(&sach1, &student, date(2024, 3, 6), date(2024, 4, 6));
+        assert_eq!(loans.data.len(), 3);
+
+        let all = Period::default();
+        let keys = |counts: Vec<LoanCount>| {
+            counts
+                .into_iter()
+                .map(|c| (c.key, c.loans))
+                .collect::<Vec<_>>()
+        };
+        let count = |group| loans.count(group, all, &db.books, &db.categories);
+        assert_eq!(
+            keys(count(LoanGroup::Month)),
+            [("2024-01".into(), 1), ("2024-03".into(), 2)]
+        );
+        assert_eq!(
+            keys(count(LoanGroup::Section)),
+            [("Belletristik".into(), 2), ("Sachbuch".into(), 1)]
+        );
+        assert_eq!(
+            keys(count(LoanGroup::Role)),
+            [("5a".into(), 2), ("Lehrer".into(), 1)]
+        );
+        assert_eq!(
+            keys(count(LoanGroup::Title)),
+            [("FANT 1".into(), 2), ("SACH 1".into(), 1)]
+        );
+
+        let march = Period {
+            from: Some(date(2024, 3, 1)),
+            to: Some(date(2024, 3, 31)),
+        };
+        assert_eq!(
+            keys(loans.count(LoanGroup::Category, march, &db.books, &db.categories)),
+            [("FANT".into(), 1), ("SACH".into(), 1)]
+        );
+
+        let summary = loans.summary(all, date(2024, 5, 1));
+        assert_eq!(summary.loans, 3);
+        assert_eq!(summary.returned, 2);
+        assert_eq!(summary.average_days, (41.0 + 41.0) / 2.0);
+        // returned late and still open past the deadline
+        assert_eq!(summary.overdue, 2);
+
+        let never = loans.never_borrowed(1, &db.books, date(2024, 12, 31));
+        assert_eq!(never.len(), 1);
+        assert_eq!(never[0].id, "FANT 2");
+
+        // swap the ids
+        loans.rename(&[
+            IdChange {
+                old: "FANT 1".into(),
+                new: "SACH 1".into(),
+            },
+            IdChange {
+                old: "SACH 1".into(),
+                new: "FANT 1".into(),
+            },
+        ]);
+        let ids = loans
+            .data
+            .iter()
+            .map(|l| l.book.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["SACH 1", "SACH 1", "FANT 1"]);
+    }
+}
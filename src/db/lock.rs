@@ -0,0 +1,105 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use tracing::error;
+
+use crate::error::{Error, Result};
+
+/// Advisory lock on a file next to the database.
+///
+/// The lock prevents multiple instances from using the same database.
+/// It is held by the operating system, which also releases it if the process crashes.
+/// The file only contains the PID of its owner for diagnostics.
+#[derive(Debug)]
+pub struct Lock {
+    path: PathBuf,
+    file: File,
+}
+
+impl Lock {
+    /// Acquires the lock of the database at `path`.
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let path = lock_path(path);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                // might not be readable while locked on some platforms
+                let owner = fs::read_to_string(&path).unwrap_or_default();
+                error!(
+                    "The database is locked by the process {:?} ({path:?})",
+                    owner.trim()
+                );
+                return Err(Error::FileOpen);
+            }
+            Err(TryLockError::Error(e)) => {
+                error!("Failed to lock the database ({path:?}): {e}");
+                return Err(Error::FileOpen);
+            }
+        }
+        file.set_len(0)?;
+        write!(file, "{}", process::id())?;
+        file.sync_all()?;
+        Ok(Self { path, file })
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // The file is kept, as removing it would race with processes that
+        // have just opened it. Closing the file releases the lock.
+        if let Err(e) = self.file.set_len(0) {
+            error!("Failed to clear lock {:?}: {e}", self.path);
+        }
+    }
+}
+
+/// Path of the lock that belongs to the database file.
+pub fn lock_path(path: &Path) -> PathBuf {
+    path.with_extension("lock")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn exclusive_locks() {
+        let dir = env::temp_dir().join(format!("schiller-lock-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("lib.json");
+        let path = lock_path(&db);
+
+        let lock = Lock::acquire(&db).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            process::id().to_string()
+        );
+        // also held against other handles of the same process
+        assert!(matches!(Lock::acquire(&db), Err(Error::FileOpen)));
+        drop(lock);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        // the content of the file does not matter, e.g., after a crash
+        for pid in ["4194305", "foo"] {
+            fs::write(&path, pid).unwrap();
+            let lock = Lock::acquire(&db).unwrap();
+            assert_eq!(
+                fs::read_to_string(&path).unwrap(),
+                process::id().to_string()
+            );
+            drop(lock);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,12 +1,14 @@
 use std::fmt;
 use std::fs::File;
-use std::io::{BufReader, Seek};
+use std::io::BufReader;
 use std::path::Path;
 use std::str::FromStr;
 
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use tracing::info;
 
-use super::Database;
+use super::{Author, Database};
 use crate::error::{Error, Result};
 use crate::server::UserConfig;
 use crate::util::PKG_VERSION;
@@ -18,6 +20,19 @@ struct DatabaseVersion {
 }
 
 const MIN_VERSION: Version = Version(0, 9, 0);
+/// Authors changed from a comma separated string to a list of [`Author`]s
+const STRUCTURED_AUTHORS: Version = Version(0, 12, 0);
+
+/// Whether the JSON database at `path` is from an older version.
+///
+/// Legacy databases are not modified by the migration and are therefore not outdated.
+pub fn is_outdated(path: &Path) -> Result<bool> {
+    if path.extension() != Some(std::ffi::OsStr::new("json")) {
+        return Ok(false);
+    }
+    let DatabaseVersion { version } = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    Ok(version < PKG_VERSION.parse().unwrap())
+}
 
 pub fn import(path: &Path, #[allow(unused)] user: Option<&UserConfig>) -> Result<Database> {
     #[cfg(feature = "sqlite")]
@@ -26,17 +41,87 @@ pub fn import(path: &Path, #[allow(unused)] user: Option<&UserConfig>) -> Result
         return from_db(path, user);
     }
 
-    let mut file = File::open(path)?;
+    let mut db = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    for step in migrate(&mut db)? {
+        info!(
+            "Migrated to {}: {} ({} changes)",
+            step.version, step.description, step.changes
+        );
+    }
+    Ok(serde_json::from_value(db)?)
+}
+
+/// Returns the migration steps for the database at `path`, without modifying it.
+///
+/// Fails if the migrated database is invalid.
+pub fn dry_run(path: &Path) -> Result<Vec<MigrationStep>> {
+    let mut db = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let steps = migrate(&mut db)?;
+    serde_json::from_value::<Database>(db)?;
+    Ok(steps)
+}
+
+/// Upgrade of the raw database to a new version
+struct Migration {
+    version: Version,
+    description: &'static str,
+    /// Returns the number of changed records
+    apply: fn(&mut Value) -> usize,
+}
+
+/// All migrations, ordered by their version
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: STRUCTURED_AUTHORS,
+    description: "Split the comma separated authors",
+    apply: migrate_authors,
+}];
+
+/// Applied migration
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStep {
+    pub version: Version,
+    pub description: &'static str,
+    /// Number of changed records
+    pub changes: usize,
+}
 
-    let DatabaseVersion { version } = serde_json::from_reader(BufReader::new(&file))?;
+/// Applies all migrations newer than the database and updates its version.
+fn migrate(db: &mut Value) -> Result<Vec<MigrationStep>> {
+    let DatabaseVersion { version } = DatabaseVersion::deserialize(&*db)?;
     let pkg_version: Version = PKG_VERSION.parse().unwrap();
-    if MIN_VERSION <= version && version <= pkg_version {
-        file.rewind()?;
-        // TODO: Migration routines
-        Database::load(&file)
-    } else {
-        Err(Error::UnsupportedProjectVersion)
+    if version < MIN_VERSION || version > pkg_version {
+        return Err(Error::UnsupportedProjectVersion);
     }
+    let mut steps = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| version < m.version) {
+        steps.push(MigrationStep {
+            version: migration.version.clone(),
+            description: migration.description,
+            changes: (migration.apply)(db),
+        });
+    }
+    db["version"] = Value::String(pkg_version.to_string());
+    Ok(steps)
+}
+
+/// Split the comma separated authors into their surname and forename.
+fn migrate_authors(db: &mut Value) -> usize {
+    let Some(books) = db.get_mut("books").and_then(|b| b.as_object_mut()) else {
+        return 0;
+    };
+    let mut changes = 0;
+    for book in books.values_mut() {
+        if let Some(Value::String(authors)) = book.get("authors") {
+            let authors = authors
+                .split(',')
+                .filter(|a| !a.trim().is_empty())
+                .map(Author::parse)
+                .collect::<Vec<_>>();
+            book["authors"] = serde_json::to_value(authors).unwrap();
+            changes += 1;
+        }
+    }
+    changes
 }
 
 #[cfg(feature = "sqlite")]
@@ -258,4 +343,64 @@ mod tests {
 
         assert!(PKG_VERSION.parse::<Version>().is_ok());
     }
+
+    #[test]
+    fn structured_authors() {
+        let mut db = serde_json::json!({
+            "books": {
+                "FANT PAOL 1": { "authors": "Christopher Paolini, Melissa de la Cruz" },
+                "FANT XXXX 1": { "authors": "" },
+            }
+        });
+        assert_eq!(migrate_authors(&mut db), 2);
+        assert_eq!(
+            db["books"]["FANT PAOL 1"]["authors"],
+            serde_json::json!([
+                { "surname": "Paolini", "forename": "Christopher", "role": "Author" },
+                { "surname": "de la Cruz", "forename": "Melissa", "role": "Author" },
+            ])
+        );
+        assert_eq!(db["books"]["FANT XXXX 1"]["authors"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn fixtures() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/migrate");
+        let pkg_version: Version = PKG_VERSION.parse().unwrap();
+
+        let path = dir.join("0.11.0.json");
+        let original = std::fs::read(&path).unwrap();
+        let steps = dry_run(&path).unwrap();
+        assert_eq!(
+            steps,
+            [MigrationStep {
+                version: STRUCTURED_AUTHORS,
+                description: "Split the comma separated authors",
+                changes: 2,
+            }]
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+        assert!(is_outdated(&path).unwrap());
+
+        let db = import(&path, None).unwrap();
+        assert_eq!(db.version, pkg_version);
+        let book = db.books.fetch("FANT PAOL 1").unwrap();
+        assert_eq!(book.authors, [Author::parse("Christopher Paolini")]);
+        assert!(db.books.fetch("FANT XXXX 1").unwrap().authors.is_empty());
+
+        let path = dir.join("0.12.0.json");
+        assert!(dry_run(&path).unwrap().is_empty());
+        let db = import(&path, None).unwrap();
+        let book = db.books.fetch("FANT PAOL 1").unwrap();
+        assert_eq!(book.condition, super::super::Condition::Damaged);
+        assert_eq!(db.loans.data.len(), 1);
+    }
+
+    #[test]
+    fn unsupported() {
+        for version in ["0.8.9", "255.0.0", "1.x.0"] {
+            let mut db = serde_json::json!({ "version": version });
+            assert!(migrate(&mut db).is_err(), "{version}");
+        }
+    }
 }
@@ -1,20 +1,24 @@
 use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashSet};
 use std::ffi::{OsStr, OsString};
-use std::fs::File;
-use std::io::{self, BufWriter};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
 use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
-use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
-use std::{fmt, fs};
+use std::sync::{Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
 
-use chrono::{Local, NaiveDate};
+use chrono::{Days, Local, NaiveDate};
 use gluer::metadata;
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::db::sorted::Sorted;
 use crate::error::{Error, Result};
 use crate::mail::account_is_valid;
+use crate::provider::Provider;
+use crate::provider::marc::Record;
 use crate::server::UserConfig;
 use crate::util::PKG_VERSION;
 
@@ -24,7 +28,24 @@ mod user;
 pub use user::*;
 mod category;
 pub use category::*;
-mod migrate;
+mod id_scheme;
+pub use id_scheme::*;
+mod stocktaking;
+pub use stocktaking::*;
+mod loan;
+pub use loan::*;
+mod weeding;
+pub use weeding::*;
+mod storage;
+pub use storage::*;
+mod backup;
+pub use backup::*;
+mod lock;
+pub use lock::*;
+mod catalogue;
+pub use catalogue::*;
+pub mod integrity;
+pub mod migrate;
 pub use migrate::Version;
 pub mod sorted;
 
@@ -32,6 +53,9 @@ pub mod sorted;
 #[deprecated]
 mod legacy;
 
+/// Attempts to read a consistent state of a database that is in use
+const OPEN_RETRIES: usize = 3;
+
 /// Library settings
 #[metadata]
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -57,6 +81,32 @@ pub struct Settings {
     pub mail_info: MailTemplate,
     pub mail_overdue: MailTemplate,
     pub mail_overdue2: MailTemplate,
+
+    /// Metadata providers in the order they are queried
+    pub providers: Vec<Provider>,
+
+    // Book ids
+    pub id_scheme: IdScheme,
+    /// Prefix of the sequential ids
+    pub id_prefix: String,
+    /// Minimal number of digits of the sequential ids
+    pub id_digits: usize,
+    /// Pattern of the custom ids, see [`Settings::id_pattern`]
+    pub id_pattern: String,
+
+    // Weeding
+    /// Minimal age in years of old books
+    pub weeding_age: usize,
+    /// Years without loans until old books are suggested for removal
+    pub weeding_idle: usize,
+    /// Maximum number of copies of a title, unlimited if zero
+    pub weeding_copies: usize,
+
+    // Union catalogue
+    /// Whether other libraries may search the catalogue
+    pub catalogue_public: bool,
+    /// Catalogues of other instances, searched together with this one
+    pub catalogues: Vec<RemoteCatalogue>,
 }
 
 /// Template for a mail notification
@@ -78,7 +128,19 @@ impl Settings {
         self.mail_overdue.body = self.mail_overdue.body.trim().to_string();
         self.mail_overdue2.subject = self.mail_overdue2.subject.trim().to_string();
         self.mail_overdue2.body = self.mail_overdue2.body.trim().to_string();
-        self.mail_from.is_empty() || account_is_valid(&self.mail_from)
+        let mut providers = Vec::with_capacity(self.providers.len());
+        for provider in &self.providers {
+            if !providers.contains(provider) {
+                providers.push(*provider);
+            }
+        }
+        self.providers = providers;
+        self.id_prefix = self.id_prefix.trim().to_string();
+        self.id_pattern = self.id_pattern.trim().to_string();
+        let catalogues_valid = self.catalogues.iter_mut().all(|c| c.validate());
+        (self.mail_from.is_empty() || account_is_valid(&self.mail_from))
+            && IdTemplate::validate(&self.id_pattern())
+            && catalogues_valid
     }
 }
 
@@ -94,6 +156,16 @@ impl Default for Settings {
             mail_info: Default::default(),
             mail_overdue: Default::default(),
             mail_overdue2: Default::default(),
+            providers: Provider::ALL.to_vec(),
+            id_scheme: IdScheme::Category,
+            id_prefix: String::new(),
+            id_digits: 6,
+            id_pattern: "{category} {author} {n}".into(),
+            weeding_age: 10,
+            weeding_idle: 5,
+            weeding_copies: 3,
+            catalogue_public: false,
+            catalogues: Vec::new(),
         }
     }
 }
@@ -111,14 +183,64 @@ pub struct Stats {
     pub overdues: usize,
 }
 
+/// Number and value of the books in a category.
+#[derive(Debug, Clone, Serialize)]
+pub struct InventoryValue {
+    pub category: String,
+    pub name: String,
+    pub section: String,
+    pub books: usize,
+    /// Sum of the book costs
+    pub value: f64,
+}
+
 /// Library database
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Database {
     version: Version,
     pub books: Books,
     pub users: Users,
     pub categories: Categories,
     settings: Settings,
+    #[serde(default, skip_serializing_if = "Loans::is_empty")]
+    pub loans: Loans,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stocktaking: Option<Stocktaking>,
+    /// The settings were modified and are not yet saved
+    #[serde(skip)]
+    settings_changed: bool,
+    /// The inventory was modified and is not yet saved
+    #[serde(skip)]
+    stocktaking_changed: bool,
+}
+
+/// Action performed for a scanned book
+#[metadata]
+#[repr(i64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ScanAction {
+    /// Lent to the user
+    Lent = 0,
+    /// Deadline extended, as it was already borrowed by the user
+    Renewed,
+    /// Returned, as it was already borrowed by the user or no user was given
+    Returned,
+}
+
+/// Result for a scanned book, either the performed action or an error
+#[metadata]
+#[derive(Debug, Clone, Serialize)]
+pub struct Scan {
+    pub id: String,
+    #[meta(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<ScanAction>,
+    #[meta(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub book: Option<Book>,
+    #[meta(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
 }
 
 /// Borrowed books that missed the deadline
@@ -159,6 +281,10 @@ impl Default for Database {
             users: Default::default(),
             categories: Default::default(),
             settings: Default::default(),
+            loans: Default::default(),
+            stocktaking: None,
+            settings_changed: false,
+            stocktaking_changed: false,
         }
     }
 }
@@ -168,6 +294,28 @@ impl Database {
     pub fn load(file: impl io::Read) -> Result<Self> {
         Ok(serde_json::from_reader(file)?)
     }
+    /// Load the database at `path` with the changes from its journal,
+    /// without locking or modifying it.
+    ///
+    /// If the running server compacts the journal in between, it is read again,
+    /// as the journal would be missing. Replaying it twice is harmless.
+    pub fn open(path: &Path) -> Result<Self> {
+        let version = |path: &Path| -> Result<_> {
+            let metadata = fs::metadata(path)?;
+            Ok((metadata.modified()?, metadata.len()))
+        };
+        for _ in 0..OPEN_RETRIES {
+            let before = version(path)?;
+            let mut db = migrate::import(path, None)?;
+            replay(&journal_path(&path.with_extension("json")), &mut db)?;
+            if version(path)? == before {
+                db.changes();
+                return Ok(db);
+            }
+        }
+        error!("The database {path:?} changed while reading it");
+        Err(Error::FileOpen)
+    }
     /// Save this database to a file
     pub fn save(&self, file: impl io::Write) -> Result<()> {
         let writer = BufWriter::new(file);
@@ -180,6 +328,10 @@ impl Database {
         settings.mail_password.clear(); // Hide the password
         settings
     }
+    /// Return the library settings including the mail password, for sending mails
+    pub fn mail_settings(&self) -> &Settings {
+        &self.settings
+    }
     /// Save the given settings to the database
     pub fn settings_update(&mut self, mut settings: Settings) -> Result<Settings> {
         if settings.validate() {
@@ -189,6 +341,7 @@ impl Database {
                 settings.mail_password = self.settings.mail_password.clone();
             }
             self.settings = settings.clone();
+            self.settings_changed = true;
             settings.mail_password.clear(); // Hide the password
             Ok(settings)
         } else {
@@ -229,6 +382,33 @@ impl Database {
         })
     }
 
+    /// Return the number and value of the books per category
+    pub fn inventory(&self) -> Result<Vec<InventoryValue>> {
+        let mut values: Vec<InventoryValue> = self
+            .categories
+            .data
+            .values()
+            .map(|c| InventoryValue {
+                category: c.id.clone(),
+                name: c.name.clone(),
+                section: c.section.clone(),
+                books: 0,
+                value: 0.0,
+            })
+            .collect();
+
+        for book in self.books.data.values() {
+            if let Ok(i) = values.binary_search_by(|v| v.category.as_str().cmp(&book.category)) {
+                values[i].books += 1;
+                values[i].value += book.costs;
+            }
+        }
+        for value in &mut values {
+            value.value = (value.value * 100.0).round() / 100.0;
+        }
+        Ok(values)
+    }
+
     /// Lend the given book (`id`) to the `account`
     pub fn lend(&mut self, id: &str, account: &str, deadline: NaiveDate) -> Result<Book> {
         let mut book = self.books.fetch(id)?;
@@ -237,7 +417,7 @@ impl Database {
         if !user.may_borrow {
             return Err(Error::LendingUserMayNotBorrow);
         }
-        if !book.borrowable {
+        if !book.borrowable || book.condition == Condition::Lost {
             return Err(Error::LendingBookNotBorrowable);
         }
         // Allow renewal
@@ -246,7 +426,7 @@ impl Database {
         }
         if let Some(reservation) = &book.reservation {
             if *reservation == user.account {
-                book = self.release(id)?; // Allow lending to reserver
+                book.reservation = None; // Allow lending to reserver
             } else {
                 return Err(Error::LendingBookAlreadyReserved);
             }
@@ -256,7 +436,10 @@ impl Database {
             user: user.account.clone(),
             deadline,
         });
-        self.books.update(id, book, &self.categories)
+        let book = self.books.update(id, book, &self.categories, &self.users)?;
+        let today = Local::now().naive_local().date();
+        self.loans.lend(&book, &user, today, deadline);
+        Ok(book)
     }
     /// Returns the book.
     pub fn return_back(&mut self, id: &str) -> Result<Book> {
@@ -267,7 +450,10 @@ impl Database {
         }
 
         book.borrower = None;
-        self.books.update(id, book, &self.categories)
+        let book = self.books.update(id, book, &self.categories, &self.users)?;
+        self.loans
+            .return_back(&book.id, Local::now().naive_local().date());
+        Ok(book)
     }
     /// Creates a reservation for the borrowed book.
     pub fn reserve(&mut self, id: &str, account: &str) -> Result<Book> {
@@ -295,7 +481,7 @@ impl Database {
         }
 
         book.reservation = Some(user.account.clone());
-        self.books.update(id, book, &self.categories)
+        self.books.update(id, book, &self.categories, &self.users)
     }
     /// Removes the reservation from the specified book.
     pub fn release(&mut self, id: &str) -> Result<Book> {
@@ -306,7 +492,180 @@ impl Database {
         }
 
         book.reservation = None;
-        self.books.update(id, book, &self.categories)
+        self.books.update(id, book, &self.categories, &self.users)
+    }
+
+    /// Starts a new inventory for the given categories, all if empty.
+    pub fn stocktaking_start(&mut self, categories: Vec<String>) -> Result<StocktakingReport> {
+        if self.stocktaking.is_some() {
+            return Err(Error::Duplicate);
+        }
+        for category in &categories {
+            let category = category.trim();
+            if !category.is_empty() && !self.categories.data.contains_key(category) {
+                return Err(Error::Arguments);
+            }
+        }
+        self.stocktaking_changed = true;
+        let stocktaking = self.stocktaking.insert(Stocktaking::new(categories));
+        Ok(stocktaking.report(&self.books))
+    }
+    /// Records the ids scanned on the shelf of the `category`.
+    ///
+    /// Lost books that are scanned again are no longer lost.
+    pub fn stocktaking_scan(
+        &mut self,
+        category: &str,
+        ids: &[String],
+    ) -> Result<StocktakingReport> {
+        let stocktaking = self.stocktaking.as_mut().ok_or(Error::NothingFound)?;
+        stocktaking.scan(category, ids);
+        self.stocktaking_changed = true;
+        for id in ids {
+            let id = id.trim();
+            if let Some(book) = self.books.data.get_mut(id)
+                && book.condition == Condition::Lost
+            {
+                book.condition = Condition::Good;
+                self.books.touch(id);
+            }
+        }
+        Ok(stocktaking.report(&self.books))
+    }
+    /// Returns the report of the running inventory.
+    pub fn stocktaking_report(&self) -> Result<StocktakingReport> {
+        let stocktaking = self.stocktaking.as_ref().ok_or(Error::NothingFound)?;
+        Ok(stocktaking.report(&self.books))
+    }
+    /// Marks the missing books of the running inventory as lost.
+    pub fn stocktaking_lost(&mut self) -> Result<usize> {
+        let stocktaking = self.stocktaking.as_ref().ok_or(Error::NothingFound)?;
+        stocktaking.mark_lost(&mut self.books)
+    }
+    /// Updates the references to changed book ids, all changes are applied at once.
+    pub fn rename_references(&mut self, changes: &[IdChange]) {
+        self.loans.rename(changes);
+        if let Some(stocktaking) = &mut self.stocktaking
+            && stocktaking.rename(changes)
+        {
+            self.stocktaking_changed = true;
+        }
+    }
+    /// Merges the `source` book into the book with the given `id`,
+    /// moving the loans of `source` along.
+    pub fn merge_books(&mut self, id: &str, source: &str) -> Result<Book> {
+        let book = self.books.merge(id, source)?;
+        self.rename_references(&[IdChange {
+            old: source.trim().into(),
+            new: book.id.clone(),
+        }]);
+        Ok(book)
+    }
+    /// Ends the running inventory.
+    pub fn stocktaking_finish(&mut self) -> Result<()> {
+        self.stocktaking_changed = true;
+        self.stocktaking
+            .take()
+            .map(|_| ())
+            .ok_or(Error::NothingFound)
+    }
+
+    /// Returns the books that are suggested for removal.
+    pub fn weeding(&self) -> Vec<WeedingCandidate> {
+        let today = Local::now().naive_local().date();
+        weeding(&self.books, &self.loans, &self.settings, today)
+    }
+    /// Removes the given books from the library.
+    ///
+    /// Either all or none of the books are removed.
+    /// The loan history of the books is kept.
+    pub fn withdraw(&mut self, ids: &[String]) -> Result<usize> {
+        let ids = ids.iter().map(|id| id.trim()).collect::<BTreeSet<_>>();
+        for id in &ids {
+            let book = self.books.fetch(id)?;
+            if book.borrower.is_some() {
+                return Err(Error::LendingBookAlreadyBorrowed);
+            }
+            if book.reservation.is_some() {
+                return Err(Error::LendingBookAlreadyReserved);
+            }
+        }
+        for id in &ids {
+            self.books.delete(id)?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Lends all scanned books to the user with the configured borrowing duration.
+    ///
+    /// Books already borrowed by the user are renewed if `renew` is set and
+    /// returned otherwise. Without an `account` all books are returned.
+    /// Failures are reported for each book, the other books are still processed.
+    /// Repeated ids, e.g., from double reads of the scanner, are processed once.
+    pub fn scan(&mut self, account: &str, ids: &[String], renew: bool) -> Result<Vec<Scan>> {
+        let account = account.trim();
+        if !account.is_empty() {
+            let user = self.users.fetch(account)?;
+            if !user.may_borrow {
+                return Err(Error::LendingUserMayNotBorrow);
+            }
+        }
+        let today = Local::now().naive_local().date();
+        let deadline = today + Days::new(self.settings.borrowing_duration as _);
+
+        let mut results = Vec::with_capacity(ids.len());
+        let mut scanned = HashSet::new();
+        for id in ids {
+            let id = id.trim();
+            if !scanned.insert(id) {
+                continue;
+            }
+            let result = self.books.fetch(id).and_then(|book| {
+                let borrowed = book.borrower.is_some_and(|b| b.user == account);
+                if account.is_empty() || borrowed && !renew {
+                    Ok((ScanAction::Returned, self.return_back(id)?))
+                } else if borrowed {
+                    Ok((ScanAction::Renewed, self.lend(id, account, deadline)?))
+                } else {
+                    Ok((ScanAction::Lent, self.lend(id, account, deadline)?))
+                }
+            });
+            results.push(match result {
+                Ok((action, book)) => Scan {
+                    id: id.into(),
+                    action: Some(action),
+                    book: Some(book),
+                    error: None,
+                },
+                Err(error) => Scan {
+                    id: id.into(),
+                    action: None,
+                    book: None,
+                    error: Some(error),
+                },
+            });
+        }
+        Ok(results)
+    }
+
+    /// Adds the MARC records as books of the category.
+    ///
+    /// The book ids are generated and records without a title are skipped.
+    pub fn import(&mut self, records: Vec<Record>, category: &str) -> Result<Vec<Book>> {
+        if !self.categories.data.contains_key(category) {
+            return Err(Error::Arguments);
+        }
+        let settings = self.settings();
+        let mut books = Vec::new();
+        for record in records {
+            let mut book = record.into_book(category);
+            if book.title.trim().is_empty() {
+                continue;
+            }
+            book.id = self.books.generate_id(&book, &settings, &self.categories)?;
+            books.push(self.books.add(book, &self.categories, &self.users)?);
+        }
+        Ok(books)
     }
 
     /// Return the list of expired loan periods.
@@ -331,88 +690,200 @@ impl Database {
 /// Synchronized Wrapper, that automatically saves changes
 pub struct AtomicDatabase {
     path: PathBuf,
-    /// Name of the DB temporary file
-    tmp: PathBuf,
+    /// Released after the database is saved on drop
+    _lock: Lock,
+    backups: Backups,
+    saved: Mutex<Saved>,
     data: RwLock<Database>,
 }
 
+/// Last saved state of the database, used to revert uncommitted changes
+struct Saved {
+    data: Database,
+    storage: Box<dyn Storage>,
+}
+
 impl AtomicDatabase {
     /// Load the database from the file system.
     ///
-    /// This also migrates it if it necessary.
-    pub fn load(path: &Path, user: Option<&UserConfig>) -> Result<Self> {
+    /// This also migrates it if it necessary and recovers
+    /// the changes from the journal of a previous crash.
+    /// If `recover` is set, a complete database in the temporary file
+    /// of an interrupted write replaces the database file.
+    pub fn load(
+        path: &Path,
+        user: Option<&UserConfig>,
+        storage: StorageKind,
+        recover: bool,
+    ) -> Result<Self> {
         let new_path = path.with_extension("json");
-        let tmp = Self::tmp_path(&new_path)?;
+        let lock = Lock::acquire(&new_path)?;
+        let tmp = Self::tmp_path(&new_path);
+        let backups = Backups::new(&new_path);
 
-        let data = migrate::import(path, user)?;
-        atomic_write(&tmp, &new_path, &data)?;
+        let path = if Self::recover(&new_path, &tmp, &backups, recover)? {
+            &new_path
+        } else {
+            path
+        };
+        if migrate::is_outdated(path)? {
+            backups.copy(path, BackupKind::Migration)?;
+        }
+        let mut data = migrate::import(path, user)?;
+        replay(&journal_path(&new_path), &mut data)?;
+        data.changes();
+        for issue in data.check() {
+            warn!("Integrity: {issue}");
+        }
+
+        let mut storage = storage.open(&new_path, &tmp);
+        storage.compact(&data)?;
 
         Ok(Self {
             path: new_path,
-            tmp,
+            _lock: lock,
+            backups,
+            saved: Mutex::new(Saved {
+                data: data.clone(),
+                storage,
+            }),
             data: RwLock::new(data),
         })
     }
     /// Create a new database and save it.
-    pub fn create(path: &Path) -> Result<Self> {
-        let tmp = Self::tmp_path(path)?;
+    pub fn create(path: &Path, storage: StorageKind) -> Result<Self> {
+        let lock = Lock::acquire(path)?;
+        let tmp = Self::tmp_path(path);
+        // there is no database, that could be more recent
+        if tmp.exists() {
+            warn!("Removing orphaned temporary file {tmp:?}");
+            fs::remove_file(&tmp)?;
+        }
 
         let data = Database::default();
-        atomic_write(&tmp, path, &data)?;
+        let mut storage = storage.open(path, &tmp);
+        storage.compact(&data)?;
 
         Ok(Self {
             path: path.into(),
-            tmp,
+            _lock: lock,
+            backups: Backups::new(path),
+            saved: Mutex::new(Saved {
+                data: data.clone(),
+                storage,
+            }),
             data: RwLock::new(data),
         })
     }
+    /// Path of the database file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Snapshots of the database.
+    pub fn backups(&self) -> &Backups {
+        &self.backups
+    }
+    /// Take a snapshot of the saved database.
+    pub fn backup(&self, kind: BackupKind) -> Result<Backup> {
+        let saved = self.saved.lock().unwrap();
+        self.backups.create(&saved.data, kind)
+    }
+    /// Take the daily snapshot, if not already done, and delete the outdated ones.
+    pub fn backup_daily(&self) -> Result<Option<Backup>> {
+        let saved = self.saved.lock().unwrap();
+        self.backups.daily(&saved.data)
+    }
+    /// Replace the database with the snapshot, after it has been validated.
+    ///
+    /// The current state is saved as snapshot before.
+    pub fn restore(&self, name: &str) -> Result<()> {
+        let restored = self.backups.load(name)?;
+        let mut data = self.data.write().unwrap_or_else(PoisonError::into_inner);
+        let Saved {
+            data: saved,
+            storage,
+        } = &mut *self.saved.lock().unwrap();
+        self.backups.create(saved, BackupKind::Restore)?;
+        storage.compact(&restored)?;
+        *saved = restored.clone();
+        *data = restored;
+        Ok(())
+    }
     /// Lock the database for reading.
     pub fn read(&self) -> AtomicDatabaseRead<'_> {
         AtomicDatabaseRead {
-            data: self.data.read().unwrap(),
+            // changes of panicked writers are rolled back
+            data: self.data.read().unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+    /// Runs `f` with the database locked for writing.
+    ///
+    /// The changes are saved if `f` succeeds and rolled back otherwise.
+    pub fn transaction<T>(&self, f: impl FnOnce(&mut Database) -> Result<T>) -> Result<T> {
+        let mut guard = self.write();
+        match f(&mut guard) {
+            Ok(value) => {
+                guard.commit()?;
+                Ok(value)
+            }
+            Err(e) => {
+                guard.rollback();
+                Err(e)
+            }
         }
     }
-    /// Lock the database for writing. This will save the changes atomically on drop.
+    /// Lock the database for writing.
+    ///
+    /// The changes are saved atomically on drop, unless they are rolled back.
     pub fn write(&self) -> AtomicDatabaseWrite<'_> {
         AtomicDatabaseWrite {
-            path: &self.path,
-            tmp: &self.tmp,
-            data: self.data.write().unwrap(),
+            saved: &self.saved,
+            data: self.data.write().unwrap_or_else(PoisonError::into_inner),
         }
     }
 
-    fn tmp_path(path: &Path) -> Result<PathBuf> {
+    fn tmp_path(path: &Path) -> PathBuf {
         let mut tmp_name = OsString::from(".");
         tmp_name.push(path.file_name().unwrap_or(OsStr::new("db")));
         tmp_name.push("~");
-        let tmp = path.with_file_name(tmp_name);
-        if tmp.exists() {
+        path.with_file_name(tmp_name)
+    }
+
+    /// Handles the temporary file left by a crash while writing the database.
+    ///
+    /// An incomplete temporary file is removed, as the database file is still intact.
+    /// A complete one was not yet renamed and is newer than the database file.
+    /// It replaces the database file if `recover` is set.
+    ///
+    /// Returns whether the database was recovered.
+    fn recover(path: &Path, tmp: &Path, backups: &Backups, recover: bool) -> Result<bool> {
+        if !tmp.exists() {
+            return Ok(false);
+        }
+        let complete = File::open(tmp)
+            .map_err(Error::from)
+            .and_then(|file| Database::load(BufReader::new(file)))
+            .is_ok();
+        if !complete {
+            warn!("Removing incomplete temporary file {tmp:?}");
+            fs::remove_file(tmp)?;
+            return Ok(false);
+        }
+        if !recover {
             error!(
-                "Found orphaned database temporary file '{tmp:?}'. The server has recently crashed or is already running. Delete this before continuing!"
+                "Found the complete database {tmp:?}, that was not saved due to a crash. Start with '--recover' to replace {path:?} with it, or delete it to discard it."
             );
             return Err(Error::FileOpen);
         }
-        Ok(tmp)
+        if path.exists() {
+            backups.copy(path, BackupKind::Restore)?;
+        }
+        warn!("Recovering the database from {tmp:?}");
+        fs::rename(tmp, path)?;
+        Ok(true)
     }
 }
 
-/// Atomic write routine, loosely inspired by the tempfile crate.
-///
-/// This assumes that the rename FS operations are atomic.
-fn atomic_write(tmp: &Path, path: &Path, data: &Database) -> Result<()> {
-    // Remove any existing tmp file first (from previous crash or concurrent write)
-    // This is safe because we're the only writer to this specific tmp path
-    let _ = fs::remove_file(tmp);
-
-    {
-        let mut tmpfile = File::create_new(tmp)?;
-        data.save(&mut tmpfile)?;
-        tmpfile.sync_all()?; // just to be sure!
-    }
-    fs::rename(tmp, path)?;
-    Ok(())
-}
-
 impl fmt::Debug for AtomicDatabase {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AtomicDatabase")
@@ -424,8 +895,8 @@ impl fmt::Debug for AtomicDatabase {
 impl Drop for AtomicDatabase {
     fn drop(&mut self) {
         info!("Saving database");
-        let guard = self.data.read().unwrap();
-        atomic_write(&self.tmp, &self.path, &guard).unwrap();
+        let Saved { data, storage } = self.saved.get_mut().unwrap();
+        storage.compact(data).unwrap();
     }
 }
 
@@ -440,10 +911,56 @@ impl Deref for AtomicDatabaseRead<'_> {
 }
 
 pub struct AtomicDatabaseWrite<'a> {
-    tmp: &'a Path,
-    path: &'a Path,
+    saved: &'a Mutex<Saved>,
     data: RwLockWriteGuard<'a, Database>,
 }
+impl AtomicDatabaseWrite<'_> {
+    /// Save the changes and release the lock.
+    ///
+    /// The changes are rolled back if they could not be saved.
+    pub fn commit(mut self) -> Result<()> {
+        let result = self.save();
+        if result.is_err() {
+            self.revert();
+        }
+        result
+    }
+    /// Discard the changes and release the lock.
+    pub fn rollback(mut self) {
+        self.revert();
+    }
+
+    fn revert(&mut self) {
+        let saved = self.saved.lock().unwrap();
+        self.data.revert(&saved.data);
+    }
+
+    /// Saves the changes, if there are any.
+    ///
+    /// The saved state is only updated after the changes are persisted.
+    /// Otherwise, they are kept as modifications.
+    fn save(&mut self) -> Result<()> {
+        let changes = self.data.changes();
+        if changes.is_empty() {
+            return Ok(());
+        }
+        info!("Saving database");
+        let Saved { data, storage } = &mut *self.saved.lock().unwrap();
+        // the current state is the saved one with the changes applied
+        if let Err(e) = storage.commit(&self.data, &changes) {
+            self.data.touch_changes(&changes);
+            return Err(e);
+        }
+        for change in changes {
+            if let Err(e) = data.apply(change) {
+                error!("Failed to apply saved change: {e:?}");
+                *data = self.data.clone();
+                break;
+            }
+        }
+        Ok(())
+    }
+}
 impl Deref for AtomicDatabaseWrite<'_> {
     type Target = Database;
     fn deref(&self) -> &Self::Target {
@@ -457,8 +974,13 @@ impl DerefMut for AtomicDatabaseWrite<'_> {
 }
 impl Drop for AtomicDatabaseWrite<'_> {
     fn drop(&mut self) {
-        info!("Saving database");
-        atomic_write(self.tmp, self.path, &self.data).unwrap();
+        // changes might be incomplete
+        if thread::panicking() {
+            self.revert();
+        } else if let Err(e) = self.save() {
+            error!("Failed to save the database: {e:?}");
+            self.revert();
+        }
     }
 }
 
@@ -536,4 +1058,345 @@ mod test {
 
         info!("db2: {:?}", db2.stats());
     }
+
+    #[test]
+    fn scan_books() {
+        use super::*;
+
+        let mut db = Database::default();
+        db.categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: "General".into(),
+            })
+            .unwrap();
+        for account in ["anna", "ben"] {
+            db.users
+                .add(User {
+                    account: account.into(),
+                    forename: account.into(),
+                    surname: "Test".into(),
+                    ..User::default()
+                })
+                .unwrap();
+        }
+        for id in ["FANT TOLK 1", "FANT TOLK 2"] {
+            let book = Book {
+                id: id.into(),
+                title: "Der Hobbit".into(),
+                category: "FANT".into(),
+                borrowable: true,
+                ..Book::default()
+            };
+            db.books.add(book, &db.categories, &db.users).unwrap();
+        }
+        let ids = ["FANT TOLK 1".into(), "FANT TOLK 2".into(), "MISSING".into()];
+
+        let results = db.scan("anna", &ids, false).unwrap();
+        let actions = results.iter().map(|r| r.action).collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            [Some(ScanAction::Lent), Some(ScanAction::Lent), None]
+        );
+        assert!(matches!(results[2].error, Some(Error::NothingFound)));
+        let deadline = results[0]
+            .book
+            .as_ref()
+            .unwrap()
+            .borrower
+            .as_ref()
+            .unwrap()
+            .deadline;
+        assert_eq!(
+            deadline,
+            Local::now().date_naive() + Days::new(db.settings.borrowing_duration as _)
+        );
+
+        let results = db.scan("ben", &ids[..1], false).unwrap();
+        assert!(matches!(
+            results[0].error,
+            Some(Error::LendingBookAlreadyBorrowed)
+        ));
+
+        let results = db.scan("anna", &ids[..1], true).unwrap();
+        assert_eq!(results[0].action, Some(ScanAction::Renewed));
+        let results = db.scan("anna", &ids[..1], false).unwrap();
+        assert_eq!(results[0].action, Some(ScanAction::Returned));
+        let results = db.scan("", &ids[1..2], false).unwrap();
+        assert_eq!(results[0].action, Some(ScanAction::Returned));
+        assert!(db.books.fetch("FANT TOLK 2").unwrap().borrower.is_none());
+        // the renewal did not create a new loan
+        assert_eq!(db.loans.data.len(), 2);
+        assert!(db.loans.data.iter().all(|l| l.returned.is_some()));
+
+        assert!(db.scan("nobody", &ids, false).is_err());
+
+        // double read of the scanner
+        let twice = ["FANT TOLK 1".into(), " FANT TOLK 1".into()];
+        let results = db.scan("anna", &twice, false).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].action, Some(ScanAction::Lent));
+        assert!(db.books.fetch("FANT TOLK 1").unwrap().borrower.is_some());
+    }
+
+    #[test]
+    fn write_guard() {
+        use std::{env, fs};
+
+        use super::*;
+
+        let dir = env::temp_dir().join(format!("schiller-guard-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lib.json");
+        let journal = journal_path(&path);
+        let category = |id: &str| Category {
+            id: id.into(),
+            name: id.into(),
+            section: String::new(),
+        };
+
+        let db = AtomicDatabase::create(&path, StorageKind::Journal).unwrap();
+        // unchanged and failed writes are not saved
+        drop(db.write());
+        assert!(
+            db.write()
+                .categories
+                .delete("FANT", &Books::default())
+                .is_err()
+        );
+        assert!(!journal.exists());
+
+        let mut guard = db.write();
+        guard.categories.add(category("FANT")).unwrap();
+        guard.settings_update(Settings::default()).unwrap();
+        guard.rollback();
+        assert!(db.read().categories.data.is_empty());
+        assert!(!db.write().settings_changed);
+        assert!(!journal.exists());
+
+        let mut guard = db.write();
+        guard.categories.add(category("SACH")).unwrap();
+        guard.commit().unwrap();
+        assert!(journal.exists());
+
+        // rolled back changes are restored from the saved state
+        let mut guard = db.write();
+        guard.categories.delete("SACH", &Books::default()).unwrap();
+        guard.rollback();
+        assert!(db.read().categories.data.contains_key("SACH"));
+
+        drop(db);
+        assert!(!journal.exists());
+        let db = Database::load(fs::File::open(&path).unwrap()).unwrap();
+        assert!(db.categories.data.contains_key("SACH"));
+        assert!(!db.categories.data.contains_key("FANT"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn transaction() {
+        use std::{env, fs};
+
+        use super::*;
+
+        let dir = env::temp_dir().join(format!("schiller-transaction-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let db = AtomicDatabase::create(&dir.join("lib.json"), StorageKind::Json).unwrap();
+        let user = User {
+            account: "foo.bar".into(),
+            forename: "Foo".into(),
+            surname: "Bar".into(),
+            role: "Demo".into(),
+            may_borrow: true,
+        };
+        db.transaction(|db| {
+            db.categories.add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: String::new(),
+            })?;
+            db.users.add(user.clone())?;
+            let book = Book {
+                id: "FANT 1".into(),
+                title: "Hobbit".into(),
+                category: "FANT".into(),
+                borrowable: true,
+                ..Book::default()
+            };
+            db.books.add(book, &db.categories, &db.users)
+        })
+        .unwrap();
+        let deadline = NaiveDate::from_ymd_opt(2030, 1, 1).unwrap();
+
+        // all steps are reverted if a later one fails
+        let renamed = User {
+            account: "baz.bar".into(),
+            ..user
+        };
+        let result = db.transaction(|db| {
+            db.lend("FANT 1", "foo.bar", deadline)?;
+            db.users.update("foo.bar", renamed.clone(), &mut db.books)?;
+            db.lend("FANT 2", "baz.bar", deadline)
+        });
+        assert!(matches!(result, Err(Error::NothingFound)));
+        let read = db.read();
+        assert!(read.users.data.contains_key("foo.bar"));
+        assert!(!read.users.data.contains_key("baz.bar"));
+        assert!(read.books.fetch("FANT 1").unwrap().borrower.is_none());
+        assert!(read.loans.is_empty());
+        drop(read);
+
+        db.transaction(|db| {
+            db.lend("FANT 1", "foo.bar", deadline)?;
+            db.users.update("foo.bar", renamed.clone(), &mut db.books)
+        })
+        .unwrap();
+        let read = db.read();
+        let borrower = read.books.fetch("FANT 1").unwrap().borrower.unwrap();
+        assert_eq!(borrower.user, "baz.bar");
+        assert_eq!(read.loans.data.len(), 1);
+        drop(read);
+
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_transaction() {
+        use std::panic::{self, AssertUnwindSafe};
+        use std::{env, fs};
+
+        use super::*;
+
+        let dir = env::temp_dir().join(format!("schiller-failed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lib.json");
+        let db = AtomicDatabase::create(&path, StorageKind::Json).unwrap();
+        let category = |id: &str| Category {
+            id: id.into(),
+            name: id.into(),
+            section: String::new(),
+        };
+
+        // partial changes of a panic are rolled back
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            db.transaction(|db| -> Result<()> {
+                db.categories.add(category("FANT"))?;
+                panic!("interrupted")
+            })
+        }));
+        assert!(result.is_err());
+        assert!(db.read().categories.data.is_empty());
+
+        // changes that cannot be written are rolled back
+        fs::remove_dir_all(&dir).unwrap();
+        let result = db.transaction(|db| db.categories.add(category("SACH")));
+        assert!(result.is_err());
+        assert!(db.read().categories.data.is_empty());
+        let mut guard = db.write();
+        guard.categories.add(category("SACH")).unwrap();
+        drop(guard);
+        assert!(db.read().categories.data.is_empty());
+
+        // the saved state still matches the file
+        fs::create_dir_all(&dir).unwrap();
+        db.transaction(|db| db.categories.add(category("KIND")))
+            .unwrap();
+        drop(db);
+        let saved = Database::load(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(saved.categories.data.keys().collect::<Vec<_>>(), ["KIND"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restore() {
+        use std::{env, fs};
+
+        use super::*;
+
+        let dir = env::temp_dir().join(format!("schiller-restore-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let db = AtomicDatabase::create(&dir.join("lib.json"), StorageKind::Journal).unwrap();
+        db.transaction(|db| {
+            db.categories.add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: String::new(),
+            })
+        })
+        .unwrap();
+        let backup = db.backup(BackupKind::Manual).unwrap();
+        db.transaction(|db| db.categories.delete("FANT", &db.books))
+            .unwrap();
+
+        assert!(matches!(db.restore("lib.json"), Err(Error::Arguments)));
+        db.restore(&backup.name).unwrap();
+        assert!(db.read().categories.data.contains_key("FANT"));
+        // the replaced state is kept
+        let backups = db.backups().list().unwrap();
+        assert_eq!(backups.len(), 2);
+        assert!(backups.iter().any(|b| b.kind == BackupKind::Restore));
+
+        drop(db);
+        let saved = Database::load(fs::File::open(dir.join("lib.json")).unwrap()).unwrap();
+        assert!(saved.categories.data.contains_key("FANT"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn crash_recovery() {
+        use std::{env, fs};
+
+        use super::*;
+
+        let dir = env::temp_dir().join(format!("schiller-recovery-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lib.json");
+        let tmp = dir.join(".lib.json~");
+        drop(AtomicDatabase::create(&path, StorageKind::Json).unwrap());
+
+        // only one instance at a time
+        let db = AtomicDatabase::load(&path, None, StorageKind::Json, false).unwrap();
+        assert!(lock_path(&path).exists());
+        assert!(matches!(
+            AtomicDatabase::load(&path, None, StorageKind::Json, false),
+            Err(Error::FileOpen)
+        ));
+        drop(db);
+
+        // incomplete writes are discarded
+        fs::write(&tmp, "{\"version\": \"0.12.0\", \"books\": {").unwrap();
+        drop(AtomicDatabase::load(&path, None, StorageKind::Json, false).unwrap());
+        assert!(!tmp.exists());
+
+        // complete writes are only recovered on request
+        let mut newer = Database::default();
+        newer
+            .categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: String::new(),
+            })
+            .unwrap();
+        newer.save(fs::File::create(&tmp).unwrap()).unwrap();
+        assert!(matches!(
+            AtomicDatabase::load(&path, None, StorageKind::Json, false),
+            Err(Error::FileOpen)
+        ));
+        assert!(tmp.exists());
+        let db = AtomicDatabase::load(&path, None, StorageKind::Json, true).unwrap();
+        assert!(db.read().categories.data.contains_key("FANT"));
+        assert!(!tmp.exists());
+        // the replaced database is kept
+        let backups = db.backups().list().unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].kind, BackupKind::Restore);
+
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+
+use chrono::{Local, NaiveDate};
+use gluer::metadata;
+use serde::{Deserialize, Serialize};
+
+use super::{Book, Books, Condition, IdChange};
+use crate::error::{Error, Result};
+
+/// Running inventory session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stocktaking {
+    started: NaiveDate,
+    /// Checked categories, all if empty
+    categories: Vec<String>,
+    /// Scanned ids with the category of the shelf they were found on
+    seen: BTreeMap<String, String>,
+}
+
+/// Book that was found on the shelf of another category
+#[metadata]
+#[derive(Debug, Clone, Serialize)]
+pub struct Misplaced {
+    pub book: Book,
+    /// Category of the shelf
+    pub found: String,
+}
+
+/// Current state of the inventory
+#[metadata]
+#[derive(Debug, Clone, Serialize)]
+pub struct StocktakingReport {
+    #[meta(into = String)]
+    pub started: NaiveDate,
+    pub categories: Vec<String>,
+    /// Number of scanned ids
+    pub seen: usize,
+    /// Expected books that were not seen, excluding borrowed and lost ones
+    pub missing: Vec<Book>,
+    /// Scanned ids without a book
+    pub unknown: Vec<String>,
+    /// Books found on the shelf of another category
+    pub misplaced: Vec<Misplaced>,
+}
+
+impl Stocktaking {
+    /// Starts a new session for the given categories.
+    pub fn new(categories: Vec<String>) -> Self {
+        let mut categories = categories
+            .into_iter()
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty())
+            .collect::<Vec<_>>();
+        categories.sort_unstable();
+        categories.dedup();
+        Self {
+            started: Local::now().date_naive(),
+            categories,
+            seen: BTreeMap::new(),
+        }
+    }
+
+    /// Records the scanned ids, found on the shelf of the `category`.
+    ///
+    /// The category may be empty if the shelf is not known.
+    pub fn scan(&mut self, category: &str, ids: &[String]) {
+        let category = category.trim();
+        for id in ids {
+            let id = id.trim();
+            if !id.is_empty() {
+                self.seen.insert(id.into(), category.into());
+            }
+        }
+    }
+
+    /// Updates the scanned ids, all changes are applied at once.
+    ///
+    /// Returns whether any id has changed.
+    pub fn rename(&mut self, changes: &[IdChange]) -> bool {
+        let renamed = changes
+            .iter()
+            .filter_map(|c| Some((c.new.clone(), self.seen.remove(&c.old)?)))
+            .collect::<Vec<_>>();
+        let changed = !renamed.is_empty();
+        self.seen.extend(renamed);
+        changed
+    }
+
+    fn is_expected(&self, book: &Book) -> bool {
+        (self.categories.is_empty() || self.categories.contains(&book.category))
+            && book.borrower.is_none()
+            && book.condition != Condition::Lost
+    }
+
+    /// Compares the scanned ids with the books.
+    pub fn report(&self, books: &Books) -> StocktakingReport {
+        let mut missing = Vec::new();
+        for book in books.data.values() {
+            if self.is_expected(book) && !self.seen.contains_key(&book.id) {
+                missing.push(book.clone());
+            }
+        }
+        let mut unknown = Vec::new();
+        let mut misplaced = Vec::new();
+        for (id, found) in &self.seen {
+            match books.data.get(id) {
+                Some(book) if !found.is_empty() && *found != book.category => {
+                    misplaced.push(Misplaced {
+                        book: book.clone(),
+                        found: found.clone(),
+                    })
+                }
+                Some(_) => {}
+                None => unknown.push(id.clone()),
+            }
+        }
+        StocktakingReport {
+            started: self.started,
+            categories: self.categories.clone(),
+            seen: self.seen.len(),
+            missing,
+            unknown,
+            misplaced,
+        }
+    }
+
+    /// Marks all missing books as lost.
+    ///
+    /// Returns the number of changed books.
+    pub fn mark_lost(&self, books: &mut Books) -> Result<usize> {
+        let mut lost = Vec::new();
+        for book in books.data.values_mut() {
+            if self.is_expected(book) && !self.seen.contains_key(&book.id) {
+                book.condition = Condition::Lost;
+                lost.push(book.id.clone());
+            }
+        }
+        let count = lost.len();
+        for id in lost {
+            books.touch(&id);
+        }
+        if count == 0 {
+            return Err(Error::NothingFound);
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Borrower, Category, Database, User};
+
+    #[test]
+    fn inventory() {
+        let mut db = Database::default();
+        for id in ["FANT", "SACH"] {
+            db.categories
+                .add(Category {
+                    id: id.into(),
+                    name: id.into(),
+                    section: String::new(),
+                })
+                .unwrap();
+        }
+        db.users
+            .add(User {
+                account: "foo.bar".into(),
+                forename: "Foo".into(),
+                surname: "Bar".into(),
+                role: "Demo".into(),
+                may_borrow: true,
+            })
+            .unwrap();
+        for (id, category) in [
+            ("FANT 1", "FANT"),
+            ("FANT 2", "FANT"),
+            ("FANT 3", "FANT"),
+            ("SACH 1", "SACH"),
+        ] {
+            let book = Book {
+                id: id.into(),
+                title: id.into(),
+                category: category.into(),
+                borrowable: true,
+                ..Book::default()
+            };
+            db.books.add(book, &db.categories, &db.users).unwrap();
+        }
+        let mut borrowed = db.books.fetch("FANT 3").unwrap();
+        borrowed.borrower = Some(Borrower {
+            user: "foo.bar".into(),
+            deadline: NaiveDate::from_ymd_opt(2030, 1, 1).unwrap(),
+        });
+        db.books
+            .update("FANT 3", borrowed, &db.categories, &db.users)
+            .unwrap();
+
+        let mut session = Stocktaking::new(vec![" FANT ".into(), "".into()]);
+        assert_eq!(session.categories, ["FANT"]);
+        session.scan("FANT", &["FANT 1".into(), "SACH 1".into(), "FOO".into()]);
+
+        let report = session.report(&db.books);
+        assert_eq!(report.seen, 3);
+        let ids = |books: &[Book]| books.iter().map(|b| b.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&report.missing), ["FANT 2"]);
+        assert_eq!(report.unknown, ["FOO"]);
+        assert_eq!(report.misplaced.len(), 1);
+        assert_eq!(report.misplaced[0].book.id, "SACH 1");
+
+        assert_eq!(session.mark_lost(&mut db.books).unwrap(), 1);
+        assert_eq!(db.books.fetch("FANT 2").unwrap().condition, Condition::Lost);
+        assert!(session.report(&db.books).missing.is_empty());
+        assert!(matches!(
+            session.mark_lost(&mut db.books),
+            Err(Error::NothingFound)
+        ));
+
+        // ids changed during the inventory
+        let changes = [
+            IdChange {
+                old: "FANT 1".into(),
+                new: "FANT 4".into(),
+            },
+            IdChange {
+                old: "FANT 2".into(),
+                new: "FANT 5".into(),
+            },
+        ];
+        db.books.rename(&changes).unwrap();
+        assert!(session.rename(&changes));
+        let report = session.report(&db.books);
+        assert_eq!(report.unknown, ["FOO"]);
+        assert!(report.missing.is_empty());
+        assert!(!session.rename(&changes));
+
+        // only lost books are found again
+        let mut damaged = db.books.fetch("SACH 1").unwrap();
+        damaged.condition = Condition::Damaged;
+        db.books
+            .update("SACH 1", damaged, &db.categories, &db.users)
+            .unwrap();
+        db.stocktaking_start(Vec::new()).unwrap();
+        db.stocktaking_scan("SACH", &["FANT 5".into(), "SACH 1".into()])
+            .unwrap();
+        assert_eq!(db.books.fetch("FANT 5").unwrap().condition, Condition::Good);
+        assert_eq!(
+            db.books.fetch("SACH 1").unwrap().condition,
+            Condition::Damaged
+        );
+    }
+}
@@ -0,0 +1,494 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use super::{Book, Category, Database, Loan, Settings, Stocktaking, User};
+use crate::error::{Error, Result};
+
+/// Number of journal commits after which the database is rewritten
+const COMPACT_AFTER: usize = 1000;
+
+/// Backend that persists the database
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StorageKind {
+    /// Append the modified records to a journal,
+    /// which is regularly compacted into the JSON file
+    #[default]
+    Journal,
+    /// Rewrite the whole JSON file on every change
+    Json,
+}
+
+impl StorageKind {
+    /// Creates the backend for the database at `path`.
+    pub fn open(self, path: &Path, tmp: &Path) -> Box<dyn Storage> {
+        match self {
+            StorageKind::Journal => Box::new(JournalStorage::new(path, tmp)),
+            StorageKind::Json => Box::new(JsonStorage {
+                path: path.into(),
+                tmp: tmp.into(),
+            }),
+        }
+    }
+}
+
+/// New state of a modified record, `None` if it was deleted.
+///
+/// Applying a change twice has the same effect as applying it once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Book {
+        id: String,
+        book: Option<Book>,
+    },
+    User {
+        account: String,
+        user: Option<User>,
+    },
+    Category {
+        id: String,
+        category: Option<Category>,
+    },
+    Loan {
+        index: usize,
+        loan: Loan,
+    },
+    Settings(Settings),
+    Stocktaking(Option<Stocktaking>),
+}
+
+impl Database {
+    /// Returns and resets the modifications since the last call.
+    pub fn changes(&mut self) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for id in self.books.take_changed() {
+            let book = self.books.data.get(&id).cloned();
+            changes.push(Change::Book { id, book });
+        }
+        for account in self.users.take_changed() {
+            let user = self.users.data.get(&account).cloned();
+            changes.push(Change::User { account, user });
+        }
+        for id in self.categories.take_changed() {
+            let category = self.categories.data.get(&id).cloned();
+            changes.push(Change::Category { id, category });
+        }
+        // ascending, so that new loans are appended in order
+        for index in self.loans.take_changed() {
+            if let Some(loan) = self.loans.data.get(index) {
+                changes.push(Change::Loan {
+                    index,
+                    loan: loan.clone(),
+                });
+            }
+        }
+        if std::mem::take(&mut self.settings_changed) {
+            changes.push(Change::Settings(self.settings.clone()));
+        }
+        if std::mem::take(&mut self.stocktaking_changed) {
+            changes.push(Change::Stocktaking(self.stocktaking.clone()));
+        }
+        changes
+    }
+
+    /// Reverts the modifications since the last call to [`Database::changes`],
+    /// restoring the records from the `saved` state.
+    pub fn revert(&mut self, saved: &Database) {
+        for id in self.books.take_changed() {
+            match saved.books.data.get(&id) {
+                Some(book) => self.books.data.insert(id, book.clone()),
+                None => self.books.data.remove(&id),
+            };
+        }
+        for account in self.users.take_changed() {
+            match saved.users.data.get(&account) {
+                Some(user) => self.users.data.insert(account, user.clone()),
+                None => self.users.data.remove(&account),
+            };
+        }
+        for id in self.categories.take_changed() {
+            match saved.categories.data.get(&id) {
+                Some(category) => self.categories.data.insert(id, category.clone()),
+                None => self.categories.data.remove(&id),
+            };
+        }
+        self.loans.data.truncate(saved.loans.data.len());
+        for index in self.loans.take_changed() {
+            if let Some(loan) = saved.loans.data.get(index) {
+                self.loans.data[index] = loan.clone();
+            }
+        }
+        if std::mem::take(&mut self.settings_changed) {
+            self.settings = saved.settings.clone();
+        }
+        if std::mem::take(&mut self.stocktaking_changed) {
+            self.stocktaking = saved.stocktaking.clone();
+        }
+    }
+
+    /// Records the `changes` as modifications again, e.g., if they could not be saved.
+    pub fn touch_changes(&mut self, changes: &[Change]) {
+        for change in changes {
+            match change {
+                Change::Book { id, .. } => self.books.touch(id),
+                Change::User { account, .. } => self.users.touch(account),
+                Change::Category { id, .. } => self.categories.touch(id),
+                Change::Loan { index, .. } => self.loans.touch(*index),
+                Change::Settings(_) => self.settings_changed = true,
+                Change::Stocktaking(_) => self.stocktaking_changed = true,
+            }
+        }
+    }
+
+    /// Applies a change, without recording it as modification.
+    pub fn apply(&mut self, change: Change) -> Result<()> {
+        match change {
+            Change::Book {
+                id,
+                book: Some(book),
+            } => {
+                self.books.data.insert(id, book);
+            }
+            Change::Book { id, book: None } => {
+                self.books.data.remove(&id);
+            }
+            Change::User {
+                account,
+                user: Some(user),
+            } => {
+                self.users.data.insert(account, user);
+            }
+            Change::User {
+                account,
+                user: None,
+            } => {
+                self.users.data.remove(&account);
+            }
+            Change::Category {
+                id,
+                category: Some(category),
+            } => {
+                self.categories.data.insert(id, category);
+            }
+            Change::Category { id, category: None } => {
+                self.categories.data.remove(&id);
+            }
+            Change::Loan { index, loan } => {
+                if let Some(old) = self.loans.data.get_mut(index) {
+                    *old = loan;
+                } else if index == self.loans.data.len() {
+                    self.loans.data.push(loan);
+                } else {
+                    return Err(Error::InvalidFormat);
+                }
+            }
+            Change::Settings(settings) => self.settings = settings,
+            Change::Stocktaking(stocktaking) => self.stocktaking = stocktaking,
+        }
+        Ok(())
+    }
+}
+
+/// Persistence backend of the [`super::AtomicDatabase`]
+pub trait Storage: Send {
+    /// Persists the `changes`, that are already applied to `db`.
+    fn commit(&mut self, db: &Database, changes: &[Change]) -> Result<()>;
+    /// Writes the whole database to the JSON file.
+    fn compact(&mut self, db: &Database) -> Result<()>;
+}
+
+/// Rewrites the JSON file on every commit
+pub struct JsonStorage {
+    path: PathBuf,
+    tmp: PathBuf,
+}
+
+impl Storage for JsonStorage {
+    fn commit(&mut self, db: &Database, _changes: &[Change]) -> Result<()> {
+        self.compact(db)
+    }
+    fn compact(&mut self, db: &Database) -> Result<()> {
+        atomic_write(&self.tmp, &self.path, db)?;
+        remove_journal(&journal_path(&self.path))
+    }
+}
+
+/// Appends each commit as a single line to the journal.
+///
+/// The journal is merged into the JSON file after [`COMPACT_AFTER`] commits
+/// and on shutdown.
+pub struct JournalStorage {
+    path: PathBuf,
+    tmp: PathBuf,
+    journal: PathBuf,
+    file: Option<File>,
+    commits: usize,
+}
+
+impl JournalStorage {
+    fn new(path: &Path, tmp: &Path) -> Self {
+        Self {
+            path: path.into(),
+            tmp: tmp.into(),
+            journal: journal_path(path),
+            file: None,
+            commits: 0,
+        }
+    }
+}
+
+impl Storage for JournalStorage {
+    fn commit(&mut self, db: &Database, changes: &[Change]) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        if self.commits >= COMPACT_AFTER {
+            return self.compact(db);
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.journal)?,
+            ),
+        };
+        let len = file.metadata()?.len();
+        let mut line = serde_json::to_vec(changes)?;
+        line.push(b'\n');
+        if let Err(e) = file.write_all(&line).and_then(|()| file.sync_data()) {
+            // remove a partial entry, that would corrupt the following ones
+            if let Err(e) = file.set_len(len) {
+                error!("Failed to truncate the journal: {e}");
+                // rewrite the whole database with the next commit
+                self.file = None;
+                self.commits = COMPACT_AFTER;
+            }
+            return Err(e.into());
+        }
+        self.commits += 1;
+        Ok(())
+    }
+    fn compact(&mut self, db: &Database) -> Result<()> {
+        info!("Compacting database");
+        atomic_write(&self.tmp, &self.path, db)?;
+        self.file = None;
+        self.commits = 0;
+        remove_journal(&self.journal)
+    }
+}
+
+/// Path of the journal that belongs to the database file.
+pub fn journal_path(path: &Path) -> PathBuf {
+    path.with_extension("journal")
+}
+
+/// Applies the commits of the journal, if it exists.
+///
+/// An incomplete last line, from a crash while writing, is skipped.
+/// Returns the number of applied commits.
+pub fn replay(journal: &Path, db: &mut Database) -> Result<usize> {
+    let text = match fs::read_to_string(journal) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut commits = 0;
+    for line in text.split_inclusive('\n') {
+        if !line.ends_with('\n') {
+            warn!("Skipping incomplete journal entry");
+            break;
+        }
+        let changes: Vec<Change> = serde_json::from_str(line)?;
+        for change in changes {
+            db.apply(change)?;
+        }
+        commits += 1;
+    }
+    if commits > 0 {
+        info!("Recovered {commits} commits from the journal");
+    }
+    Ok(commits)
+}
+
+fn remove_journal(journal: &Path) -> Result<()> {
+    match fs::remove_file(journal) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Atomic write routine, loosely inspired by the tempfile crate.
+///
+/// This assumes that the rename FS operations are atomic.
+fn atomic_write(tmp: &Path, path: &Path, data: &Database) -> Result<()> {
+    // Remove any existing tmp file first (from previous crash or concurrent write)
+    // This is safe because we're the only writer to this specific tmp path
+    let _ = fs::remove_file(tmp);
+
+    {
+        let mut tmpfile = File::create_new(tmp)?;
+        data.save(&mut tmpfile)?;
+        tmpfile.sync_all()?; // just to be sure!
+    }
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn library() -> Database {
+        let mut db = Database::default();
+        db.categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: String::new(),
+            })
+            .unwrap();
+        db.users
+            .add(User {
+                account: "foo.bar".into(),
+                forename: "Foo".into(),
+                surname: "Bar".into(),
+                role: "Demo".into(),
+                may_borrow: true,
+            })
+            .unwrap();
+        for id in ["FANT 1", "FANT 2"] {
+            let book = Book {
+                id: id.into(),
+                title: id.into(),
+                category: "FANT".into(),
+                borrowable: true,
+                ..Book::default()
+            };
+            db.books.add(book, &db.categories, &db.users).unwrap();
+        }
+        db
+    }
+
+    fn json(db: &Database) -> String {
+        serde_json::to_string(db).unwrap()
+    }
+
+    #[test]
+    fn changes() {
+        let mut db = library();
+        let changes = db.changes();
+        assert_eq!(changes.len(), 4);
+        assert!(db.changes().is_empty());
+
+        let deadline = NaiveDate::from_ymd_opt(2030, 1, 1).unwrap();
+        db.lend("FANT 1", "foo.bar", deadline).unwrap();
+        db.books.delete("FANT 2").unwrap();
+        let changes = db.changes();
+        assert!(matches!(
+            &changes[..],
+            [
+                Change::Book { book: Some(_), .. },
+                Change::Book { book: None, .. },
+                Change::Loan { index: 0, .. },
+            ]
+        ));
+
+        let mut copy = library();
+        for change in changes {
+            copy.apply(change).unwrap();
+        }
+        assert_eq!(json(&copy), json(&db));
+
+        // loans have to be appended in order
+        let loan = db.loans.data[0].clone();
+        assert!(matches!(
+            copy.apply(Change::Loan { index: 5, loan }),
+            Err(Error::InvalidFormat)
+        ));
+    }
+
+    #[test]
+    fn journal() {
+        let dir = env::temp_dir().join(format!("schiller-journal-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lib.json");
+        let tmp = dir.join(".lib.json~");
+        let journal = journal_path(&path);
+
+        let mut db = library();
+        db.changes();
+        let mut storage = StorageKind::Journal.open(&path, &tmp);
+        storage.compact(&db).unwrap();
+        assert!(!journal.exists());
+
+        let deadline = NaiveDate::from_ymd_opt(2030, 1, 1).unwrap();
+        db.lend("FANT 1", "foo.bar", deadline).unwrap();
+        let changes = db.changes();
+        storage.commit(&db, &changes).unwrap();
+        db.return_back("FANT 1").unwrap();
+        let changes = db.changes();
+        storage.commit(&db, &changes).unwrap();
+        // simulate a crash while writing
+        let mut file = OpenOptions::new().append(true).open(&journal).unwrap();
+        file.write_all(b"[{\"book\":").unwrap();
+        drop(storage);
+
+        let mut loaded = Database::load(File::open(&path).unwrap()).unwrap();
+        assert!(loaded.loans.is_empty());
+        assert_eq!(replay(&journal, &mut loaded).unwrap(), 2);
+        assert_eq!(json(&loaded), json(&db));
+
+        // corrupted entries are not skipped
+        fs::write(&journal, "[{\"book\":\n").unwrap();
+        assert!(replay(&journal, &mut loaded).is_err());
+        let mut text = fs::read_to_string(&journal).unwrap();
+        text.insert_str(0, "[]\n");
+        text.push_str("[]\n");
+        fs::write(&journal, text).unwrap();
+        assert!(replay(&journal, &mut loaded).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_journal_write() {
+        let dir = env::temp_dir().join(format!("schiller-failed-journal-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lib.json");
+        let journal = journal_path(&path);
+
+        let mut db = library();
+        db.changes();
+        let mut storage = JournalStorage::new(&path, &dir.join(".lib.json~"));
+        storage.compact(&db).unwrap();
+
+        let deadline = NaiveDate::from_ymd_opt(2030, 1, 1).unwrap();
+        db.lend("FANT 1", "foo.bar", deadline).unwrap();
+        let changes = db.changes();
+        storage.commit(&db, &changes).unwrap();
+
+        // neither writable nor truncatable
+        storage.file = Some(File::open(&journal).unwrap());
+        db.return_back("FANT 1").unwrap();
+        let changes = db.changes();
+        assert!(storage.commit(&db, &changes).is_err());
+
+        // the next commit rewrites the database
+        storage.commit(&db, &changes).unwrap();
+        assert!(!journal.exists());
+        let loaded = Database::load(File::open(&path).unwrap()).unwrap();
+        assert_eq!(json(&loaded), json(&db));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
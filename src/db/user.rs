@@ -1,5 +1,5 @@
-use std::collections::BTreeMap;
 use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use gluer::metadata;
 use serde::{Deserialize, Serialize};
@@ -40,7 +40,7 @@ impl Default for User {
 }
 
 impl User {
-    fn validate(&mut self) -> bool {
+    pub(super) fn validate(&mut self) -> bool {
         self.account = self.account.trim().to_string();
         self.forename = self.forename.trim().to_string();
         self.surname = self.surname.trim().to_string();
@@ -80,13 +80,26 @@ impl Default for UserSearch {
     }
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Users {
+    /// Direct modifications have to be recorded with [`Users::touch`]
     #[serde(flatten)]
     pub data: BTreeMap<String, User>,
+    /// Accounts of the modified users, that are not yet saved
+    #[serde(skip)]
+    changed: BTreeSet<String>,
 }
 
 impl Users {
+    /// Record the modification of the user, to be saved with the next commit.
+    pub fn touch(&mut self, account: &str) {
+        self.changed.insert(account.into());
+    }
+    /// Returns and resets the accounts of the modified users.
+    pub(super) fn take_changed(&mut self) -> BTreeSet<String> {
+        std::mem::take(&mut self.changed)
+    }
+
     pub fn fetch(&self, account: &str) -> Result<User> {
         let account = account.trim();
         if account.is_empty() {
@@ -103,6 +116,7 @@ impl Users {
         match self.data.entry(user.account.clone()) {
             Entry::Vacant(v) => {
                 v.insert(user.clone());
+                self.touch(&user.account);
                 Ok(user)
             }
             _ => Err(Error::Duplicate),
@@ -118,14 +132,17 @@ impl Users {
         if account == user.account {
             if let Some(entry) = self.data.get_mut(account) {
                 *entry = user.clone();
+                self.touch(account);
                 return Ok(user);
             }
         } else if self.data.contains_key(account) {
             return match self.data.entry(user.account.clone()) {
                 Entry::Vacant(v) => {
                     v.insert(user.clone());
+                    self.touch(&user.account);
                     books.update_user(account, &user.account)?;
                     self.data.remove(account);
+                    self.touch(account);
                     Ok(user)
                 }
                 _ => Err(Error::Duplicate),
@@ -144,10 +161,9 @@ impl Users {
             return Err(Error::ReferencedUser);
         }
 
-        self.data
-            .remove(account)
-            .map(|_| ())
-            .ok_or(Error::NothingFound)
+        self.data.remove(account).ok_or(Error::NothingFound)?;
+        self.touch(account);
+        Ok(())
     }
 
     /// Performes a simple user search with the given `text`.
@@ -195,16 +211,15 @@ impl Users {
     ///
     /// The roles of all users not contained in the given list are cleared.
     pub fn update_roles(&mut self, users: impl Iterator<Item = (String, String)>) -> Result<()> {
-        for user in self.data.values_mut() {
-            user.role.clear();
-        }
-
+        let mut roles = HashMap::new();
         for (account, role) in users {
-            let account = account.trim();
-            if !account.is_empty()
-                && let Some(entry) = self.data.get_mut(account)
-            {
-                entry.role = role.clone();
+            roles.insert(account.trim().to_string(), role);
+        }
+        for user in self.data.values_mut() {
+            let role = roles.remove(&user.account).unwrap_or_default();
+            if user.role != role {
+                user.role = role;
+                self.changed.insert(user.account.clone());
             }
         }
         Ok(())
@@ -0,0 +1,260 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Datelike, Months, NaiveDate};
+use gluer::metadata;
+use serde::Serialize;
+
+use super::{Book, Books, Condition, Loans, Settings};
+use crate::isbn;
+
+/// Reason for suggesting the removal of a book
+#[metadata]
+#[repr(i64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum WeedingReason {
+    /// Older than the configured age and not lent for a long time
+    Old = 0,
+    /// Marked as damaged
+    Damaged,
+    /// Marked as lost
+    Lost,
+    /// More copies of the title than configured
+    Copies,
+}
+
+/// Book that is suggested for removal
+#[metadata]
+#[derive(Debug, Clone, Serialize)]
+pub struct WeedingCandidate {
+    pub book: Book,
+    pub reasons: Vec<WeedingReason>,
+    /// Date of the latest loan, if any
+    #[meta(optional, into = String)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_lent: Option<NaiveDate>,
+}
+
+/// Returns the books that are candidates for removal, ordered by their id.
+///
+/// Borrowed and reserved books are never suggested.
+pub fn weeding(
+    books: &Books,
+    loans: &Loans,
+    settings: &Settings,
+    today: NaiveDate,
+) -> Vec<WeedingCandidate> {
+    let last_lent = loans.last_lent();
+    let idle_since = today
+        .checked_sub_months(Months::new(
+            u32::try_from(settings.weeding_idle)
+                .unwrap_or(u32::MAX)
+                .saturating_mul(12),
+        ))
+        .unwrap_or(NaiveDate::MIN);
+
+    let mut candidates = BTreeMap::<String, WeedingCandidate>::new();
+    let mut add = |book: &Book, reason| {
+        candidates
+            .entry(book.id.clone())
+            .or_insert_with(|| WeedingCandidate {
+                book: book.clone(),
+                reasons: Vec::new(),
+                last_lent: last_lent.get(book.id.as_str()).copied(),
+            })
+            .reasons
+            .push(reason);
+    };
+
+    let mut titles = HashMap::<String, Vec<&Book>>::new();
+    for book in books.data.values() {
+        if book.borrower.is_some() || book.reservation.is_some() {
+            continue;
+        }
+        let idle = last_lent
+            .get(book.id.as_str())
+            .or(book.added.as_ref())
+            .is_none_or(|&date| date < idle_since);
+        if book.year > 0
+            && today.year() as i64 - book.year
+                >= i64::try_from(settings.weeding_age).unwrap_or(i64::MAX)
+            && idle
+        {
+            add(book, WeedingReason::Old);
+        }
+        match book.condition {
+            Condition::Good => titles.entry(title_key(book)).or_default().push(book),
+            Condition::Damaged => add(book, WeedingReason::Damaged),
+            Condition::Lost => add(book, WeedingReason::Lost),
+        }
+    }
+
+    if settings.weeding_copies > 0 {
+        for mut copies in titles.into_values() {
+            if copies.len() > settings.weeding_copies {
+                // suggest the copies that were not lent for the longest time
+                copies.sort_by(|a, b| {
+                    let a_lent = last_lent.get(a.id.as_str());
+                    let b_lent = last_lent.get(b.id.as_str());
+                    a_lent.cmp(&b_lent).then_with(|| b.id.cmp(&a.id))
+                });
+                let surplus = copies.len() - settings.weeding_copies;
+                for book in &copies[..surplus] {
+                    add(book, WeedingReason::Copies);
+                }
+            }
+        }
+    }
+
+    candidates.into_values().collect()
+}
+
+/// Copies share the isbn or, if it is missing, the title and authors.
+fn title_key(book: &Book) -> String {
+    if let Some(isbn) = isbn::to_isbn13(&book.isbn) {
+        isbn
+    } else {
+        format!(
+            "{}\n{}",
+            book.title.trim().to_lowercase(),
+            book.author_names().to_lowercase()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Borrower, Category, Database, Loan, User};
+    use crate::error::Error;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn candidates() {
+        let mut db = Database::default();
+        db.categories
+            .add(Category {
+                id: "FANT".into(),
+                name: "Fantasy".into(),
+                section: String::new(),
+            })
+            .unwrap();
+        db.users
+            .add(User {
+                account: "foo.bar".into(),
+                forename: "Foo".into(),
+                surname: "Bar".into(),
+                ..User::default()
+            })
+            .unwrap();
+        let books = [
+            // old, never lent
+            ("FANT 1", "Alt", 1990, Condition::Good, None),
+            // old, but lent recently
+            (
+                "FANT 2",
+                "Beliebt",
+                1990,
+                Condition::Good,
+                Some(date(2024, 1, 1)),
+            ),
+            ("FANT 3", "Kaputt", 2020, Condition::Damaged, None),
+            ("FANT 4", "Weg", 2020, Condition::Lost, None),
+            // three copies, one too many
+            (
+                "FANT 5",
+                "Klassensatz",
+                2020,
+                Condition::Good,
+                Some(date(2024, 2, 1)),
+            ),
+            ("FANT 6", "Klassensatz", 2020, Condition::Good, None),
+            (
+                "FANT 7",
+                "Klassensatz",
+                2020,
+                Condition::Good,
+                Some(date(2024, 3, 1)),
+            ),
+        ];
+        for (id, title, year, condition, lent) in books {
+            let book = Book {
+                id: id.into(),
+                title: title.into(),
+                year,
+                category: "FANT".into(),
+                borrowable: true,
+                condition,
+                added: Some(date(2015, 1, 1)),
+                ..Book::default()
+            };
+            if let Some(lent) = lent {
+                db.loans.data.push(Loan {
+                    book: id.into(),
+                    title: title.into(),
+                    category: "FANT".into(),
+                    role: String::new(),
+                    lent,
+                    deadline: lent,
+                    returned: Some(lent),
+                });
+            }
+            db.books.add(book, &db.categories, &db.users).unwrap();
+        }
+        // borrowed books are ignored
+        let mut borrowed = db.books.fetch("FANT 1").unwrap();
+        borrowed.id = "FANT 8".into();
+        borrowed.borrower = Some(Borrower {
+            user: "foo.bar".into(),
+            deadline: date(2024, 1, 1),
+        });
+        db.books.add(borrowed, &db.categories, &db.users).unwrap();
+
+        let settings = Settings {
+            weeding_age: 10,
+            weeding_idle: 2,
+            weeding_copies: 2,
+            ..Settings::default()
+        };
+        let candidates = weeding(&db.books, &db.loans, &settings, date(2024, 6, 1));
+        let reasons = candidates
+            .iter()
+            .map(|c| (c.book.id.as_str(), c.reasons.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reasons,
+            [
+                ("FANT 1", vec![WeedingReason::Old]),
+                ("FANT 3", vec![WeedingReason::Damaged]),
+                ("FANT 4", vec![WeedingReason::Lost]),
+                ("FANT 6", vec![WeedingReason::Copies]),
+            ]
+        );
+
+        let unlimited = Settings {
+            weeding_copies: 0,
+            ..settings
+        };
+        let candidates = weeding(&db.books, &db.loans, &unlimited, date(2024, 6, 1));
+        assert_eq!(candidates.len(), 3);
+
+        // out of range settings do not overflow
+        let huge = Settings {
+            weeding_age: usize::MAX,
+            weeding_idle: usize::MAX,
+            ..unlimited
+        };
+        let candidates = weeding(&db.books, &db.loans, &huge, date(2024, 6, 1));
+        assert!(candidates.iter().all(|c| c.reasons != [WeedingReason::Old]));
+
+        assert!(matches!(
+            db.withdraw(&["FANT 1".into(), "FANT 8".into()]),
+            Err(Error::LendingBookAlreadyBorrowed)
+        ));
+        assert!(db.books.fetch("FANT 1").is_ok());
+        assert_eq!(db.withdraw(&["FANT 1".into(), "FANT 3".into()]).unwrap(), 2);
+        assert!(db.books.fetch("FANT 3").is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use std::io;
+
+use chrono::Local;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+use crate::db::{Book, InventoryValue, Overdue, User, WeedingCandidate};
+use crate::error::{Error, Result};
+
+/// File format of an export
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Comma separated values, readable by all spreadsheet programs
+    #[default]
+    Csv,
+    /// List of JSON objects
+    Json,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Json => "application/json",
+        }
+    }
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Json => "json",
+        }
+    }
+}
+
+/// Records that can be exported column by column.
+pub trait Columns {
+    /// All available columns in their default order
+    const COLUMNS: &'static [&'static str];
+    /// Returns the value of the column or `Null` if it is unknown.
+    fn column(&self, name: &str) -> Value;
+}
+
+impl Columns for Book {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "isbn",
+        "title",
+        "authors",
+        "publisher",
+        "year",
+        "costs",
+        "category",
+        "note",
+        "borrowable",
+        "condition",
+        "borrower",
+        "deadline",
+        "reservation",
+        "edition",
+        "language",
+        "pages",
+        "series",
+        "subjects",
+        "ddc",
+        "cover",
+    ];
+    fn column(&self, name: &str) -> Value {
+        match name {
+            "id" => json!(self.id),
+            "isbn" => json!(self.isbn),
+            "title" => json!(self.title),
+            "authors" => json!(self.author_names()),
+            "publisher" => json!(self.publisher),
+            "year" => json!(self.year),
+            "costs" => json!(self.costs),
+            "category" => json!(self.category),
+            "note" => json!(self.note),
+            "borrowable" => json!(self.borrowable),
+            "condition" => json!(self.condition),
+            "borrower" => json!(self.borrower.as_ref().map(|b| &b.user)),
+            "deadline" => json!(self.borrower.as_ref().map(|b| b.deadline)),
+            "reservation" => json!(self.reservation),
+            "edition" => json!(self.edition),
+            "language" => json!(self.language),
+            "pages" => json!(self.pages),
+            "series" => json!(self.series),
+            "subjects" => json!(self.subjects.join("; ")),
+            "ddc" => json!(self.ddc),
+            "cover" => json!(self.cover),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl Columns for User {
+    const COLUMNS: &'static [&'static str] =
+        &["account", "forename", "surname", "role", "may_borrow"];
+    fn column(&self, name: &str) -> Value {
+        match name {
+            "account" => json!(self.account),
+            "forename" => json!(self.forename),
+            "surname" => json!(self.surname),
+            "role" => json!(self.role),
+            "may_borrow" => json!(self.may_borrow),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl Columns for Overdue {
+    const COLUMNS: &'static [&'static str] = &[
+        "id", "title", "category", "account", "forename", "surname", "role", "deadline", "days",
+    ];
+    fn column(&self, name: &str) -> Value {
+        match name {
+            "id" => json!(self.book.id),
+            "title" => json!(self.book.title),
+            "category" => json!(self.book.category),
+            "account" => json!(self.user.account),
+            "forename" => json!(self.user.forename),
+            "surname" => json!(self.user.surname),
+            "role" => json!(self.user.role),
+            "deadline" => json!(self.book.borrower.as_ref().map(|b| b.deadline)),
+            "days" => {
+                let today = Local::now().naive_local().date();
+                json!(
+                    self.book
+                        .borrower
+                        .as_ref()
+                        .map(|b| (today - b.deadline).num_days())
+                )
+            }
+            _ => Value::Null,
+        }
+    }
+}
+
+impl Columns for InventoryValue {
+    const COLUMNS: &'static [&'static str] = &["category", "name", "section", "books", "value"];
+    fn column(&self, name: &str) -> Value {
+        match name {
+            "category" => json!(self.category),
+            "name" => json!(self.name),
+            "section" => json!(self.section),
+            "books" => json!(self.books),
+            "value" => json!(self.value),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl Columns for WeedingCandidate {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "title",
+        "authors",
+        "year",
+        "category",
+        "condition",
+        "added",
+        "last_lent",
+        "reasons",
+    ];
+    fn column(&self, name: &str) -> Value {
+        match name {
+            "id" => json!(self.book.id),
+            "title" => json!(self.book.title),
+            "authors" => json!(self.book.author_names()),
+            "year" => json!(self.book.year),
+            "category" => json!(self.book.category),
+            "condition" => json!(self.book.condition),
+            "added" => json!(self.book.added),
+            "last_lent" => json!(self.last_lent),
+            "reasons" => {
+                let reasons = self.reasons.iter().map(|r| format!("{r:?}"));
+                json!(reasons.collect::<Vec<_>>().join("; "))
+            }
+            _ => Value::Null,
+        }
+    }
+}
+
+/// Parses a comma separated list of columns.
+///
+/// Returns all columns if the list is empty.
+pub fn columns<T: Columns>(list: &str) -> Result<Vec<&'static str>> {
+    if list.trim().is_empty() {
+        return Ok(T::COLUMNS.to_vec());
+    }
+    list.split(',')
+        .map(|c| {
+            let c = c.trim();
+            T::COLUMNS
+                .iter()
+                .find(|&&known| known == c)
+                .copied()
+                .ok_or(Error::Arguments)
+        })
+        .collect()
+}
+
+/// Writes the selected columns of the records in the given format.
+pub fn write<T: Columns>(
+    writer: impl io::Write,
+    format: Format,
+    columns: &[&str],
+    records: &[T],
+) -> Result<()> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            writer.write_record(columns)?;
+            for record in records {
+                writer.write_record(columns.iter().map(|c| cell(record.column(c))))?;
+            }
+            writer.flush()?;
+        }
+        Format::Json => {
+            let rows: Vec<Map<String, Value>> = records
+                .iter()
+                .map(|r| columns.iter().map(|&c| (c.into(), r.column(c))).collect())
+                .collect();
+            serde_json::to_writer_pretty(writer, &rows)?;
+        }
+    }
+    Ok(())
+}
+
+/// Characters, that start a formula in spreadsheet programs
+const FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+fn cell(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        // text is not evaluated as formula when the file is opened
+        Value::String(s) if s.starts_with(FORMULA_PREFIXES) => format!("'{s}"),
+        Value::String(s) => s,
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Borrower;
+
+    fn book() -> Book {
+        Book {
+            id: "FANT DOE 1".into(),
+            title: "Demo, \"Test\" Book".into(),
+            year: 2020,
+            costs: 7.5,
+            borrowable: true,
+            category: "FANT".into(),
+            authors: vec!["John Doe".into()],
+            borrower: Some(Borrower {
+                user: "foo.bar".into(),
+                deadline: "2024-01-31".parse().unwrap(),
+            }),
+            ..Book::default()
+        }
+    }
+
+    #[test]
+    fn parse_columns() {
+        assert_eq!(columns::<Book>("").unwrap(), Book::COLUMNS);
+        assert_eq!(
+            columns::<Book>("id, title,costs").unwrap(),
+            ["id", "title", "costs"]
+        );
+        assert!(columns::<Book>("id,unknown").is_err());
+        assert!(columns::<User>("id").is_err());
+    }
+
+    #[test]
+    fn write_csv() {
+        let mut out = Vec::new();
+        write(
+            &mut out,
+            Format::Csv,
+            &[
+                "id",
+                "title",
+                "costs",
+                "borrower",
+                "deadline",
+                "reservation",
+            ],
+            &[book()],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "id,title,costs,borrower,deadline,reservation\n\
+            FANT DOE 1,\"Demo, \"\"Test\"\" Book\",7.5,foo.bar,2024-01-31,\n"
+        );
+    }
+
+    #[test]
+    fn escape_formulas() {
+        let book = Book {
+            title: "=HYPERLINK(\"https://example.com\")".into(),
+            note: "-".into(),
+            year: -50,
+            ..book()
+        };
+        let mut out = Vec::new();
+        write(&mut out, Format::Csv, &["title", "note", "year"], &[book]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "title,note,year\n\"'=HYPERLINK(\"\"https://example.com\"\")\",'-,-50\n"
+        );
+    }
+
+    #[test]
+    fn write_json() {
+        let mut out = Vec::new();
+        write(
+            &mut out,
+            Format::Json,
+            &["id", "year", "reservation"],
+            &[book()],
+        )
+        .unwrap();
+        let value: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            value,
+            json!([{ "id": "FANT DOE 1", "year": 2020, "reservation": null }])
+        );
+    }
+}
@@ -1,9 +1,291 @@
+use gluer::metadata;
+use serde::Serialize;
+
+/// Range of the following seven digits and the length of the element
+type Range = (&'static str, &'static str, usize);
+
+/// Lengths of the registration groups for the 978 prefix
+///
+/// See https://www.isbn-international.org/range_file_generation
+const GROUPS: &[Range] = &[
+    ("0000000", "5999999", 1),
+    ("6000000", "6499999", 3),
+    ("6500000", "6599999", 2),
+    ("7000000", "7999999", 1),
+    ("8000000", "9499999", 2),
+    ("9500000", "9899999", 3),
+    ("9900000", "9989999", 4),
+    ("9990000", "9999999", 5),
+];
+
+/// Lengths of the registrants for the english, french and german groups
+const REGISTRANTS: &[(&str, &[Range])] = &[
+    (
+        "0",
+        &[
+            ("0000000", "1999999", 2),
+            ("2000000", "6999999", 3),
+            ("7000000", "8499999", 4),
+            ("8500000", "8999999", 5),
+            ("9000000", "9499999", 6),
+            ("9500000", "9999999", 7),
+        ],
+    ),
+    (
+        "1",
+        &[
+            ("0000000", "0999999", 2),
+            ("1000000", "3999999", 3),
+            ("4000000", "5499999", 4),
+            ("5500000", "8697999", 5),
+            ("8698000", "9989999", 6),
+            ("9990000", "9999999", 7),
+        ],
+    ),
+    (
+        "2",
+        &[
+            ("0000000", "1999999", 2),
+            ("2000000", "3499999", 3),
+            ("3500000", "3999999", 5),
+            ("4000000", "6999999", 3),
+            ("7000000", "8399999", 4),
+            ("8400000", "8999999", 5),
+            ("9000000", "9499999", 6),
+            ("9500000", "9999999", 7),
+        ],
+    ),
+    (
+        "3",
+        &[
+            ("0000000", "0299999", 2),
+            ("0300000", "0339999", 3),
+            ("0340000", "0369999", 4),
+            ("0370000", "0399999", 5),
+            ("0400000", "1999999", 2),
+            ("2000000", "6999999", 3),
+            ("7000000", "8499999", 4),
+            ("8500000", "8999999", 5),
+            ("9000000", "9499999", 6),
+            ("9500000", "9539999", 7),
+            ("9540000", "9699999", 5),
+            ("9700000", "9849999", 7),
+            ("9850000", "9999999", 5),
+        ],
+    ),
+];
+
+/// Kind of a product code
+#[metadata]
+#[repr(i64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Code {
+    Isbn10,
+    Isbn13,
+    /// International Standard Music Number (979-0)
+    Ismn,
+    /// International Standard Serial Number
+    Issn,
+    /// Other EAN-13 barcodes
+    Ean,
+    /// Invalid checksum or length
+    Unknown,
+}
+
+/// Details about an isbn or other product code
+#[metadata]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Info {
+    pub code: Code,
+    /// Without invalid characters
+    pub stripped: String,
+    /// Empty if there is no isbn-10 form
+    pub isbn10: String,
+    pub isbn13: String,
+    /// Empty if the range is unknown
+    pub hyphenated: String,
+    /// Suggestion with a corrected check digit
+    pub repaired: String,
+}
+
 /// Parses the isbn and removing invalid characters.
 ///
 /// If the checksum is invalid the stripped invalid isbn is returned.
 pub fn parse(input: &str) -> Result<String, String> {
-    // Collect numeric values
-    let isbn: Vec<u8> = input
+    let isbn = digits(input);
+
+    if is10(&isbn) || is13(&isbn) {
+        Ok(isbn_str(&isbn))
+    } else {
+        Err(isbn_str(&isbn))
+    }
+}
+
+/// Converts a valid isbn-10 or isbn-13 into the isbn-13 form.
+pub fn to_isbn13(input: &str) -> Option<String> {
+    let isbn = digits(input);
+    if is13(&isbn) {
+        Some(isbn_str(&isbn))
+    } else if is10(&isbn) {
+        let mut isbn13 = vec![9, 7, 8];
+        isbn13.extend_from_slice(&isbn[..9]);
+        isbn13.push(checksum13(&isbn13));
+        Some(isbn_str(&isbn13))
+    } else {
+        None
+    }
+}
+
+/// Converts a valid isbn into the isbn-10 form, only possible for the 978 prefix.
+pub fn to_isbn10(input: &str) -> Option<String> {
+    let isbn = digits(input);
+    if is10(&isbn) {
+        Some(isbn_str(&isbn))
+    } else if is13(&isbn) && isbn.starts_with(&[9, 7, 8]) {
+        let mut isbn10 = isbn[3..12].to_vec();
+        isbn10.push(checksum10(&isbn10));
+        Some(isbn_str(&isbn10))
+    } else {
+        None
+    }
+}
+
+/// Returns if both are valid and the same isbn, regardless of their form.
+pub fn same(a: &str, b: &str) -> bool {
+    to_isbn13(a).is_some_and(|a| Some(a) == to_isbn13(b))
+}
+
+/// Inserts the hyphens according to the isbn ranges.
+///
+/// Only the english, french and german groups are supported.
+pub fn hyphenate(input: &str) -> Option<String> {
+    let isbn13 = digits(&to_isbn13(input)?);
+    if !isbn13.starts_with(&[9, 7, 8]) {
+        return None;
+    }
+    let rest = &isbn13[3..12];
+    let group = range_len(GROUPS, rest)?;
+    let group_str = isbn_str(&rest[..group]);
+    let (_, registrants) = REGISTRANTS.iter().find(|(g, _)| *g == group_str)?;
+    let registrant = range_len(registrants, &rest[group..])?;
+
+    let parts = [
+        group_str,
+        isbn_str(&rest[group..group + registrant]),
+        isbn_str(&rest[group + registrant..]),
+    ];
+    let is_isbn10 = is10(&digits(input));
+    let check = if is_isbn10 {
+        isbn_str(&[checksum10(rest)])
+    } else {
+        isbn_str(&[isbn13[12]])
+    };
+    let mut hyphenated = if is_isbn10 {
+        String::new()
+    } else {
+        "978-".into()
+    };
+    hyphenated.push_str(&parts.join("-"));
+    hyphenated.push('-');
+    hyphenated.push_str(&check);
+    Some(hyphenated)
+}
+
+/// Returns the length of the range containing the (first seven) digits.
+fn range_len(ranges: &[Range], digits: &[u8]) -> Option<usize> {
+    let mut value = isbn_str(&digits[..digits.len().min(7)]);
+    while value.len() < 7 {
+        value.push('0');
+    }
+    ranges
+        .iter()
+        .find(|(start, end, _)| (*start..=*end).contains(&value.as_str()))
+        .map(|r| r.2)
+        .filter(|&len| len > 0 && len < digits.len())
+}
+
+/// Suggests the isbn with a corrected check digit.
+///
+/// Returns `None` if the isbn is valid or the length does not match.
+pub fn repair(input: &str) -> Option<String> {
+    let mut isbn = digits(input);
+    if isbn.len() == 10 && isbn[..9].iter().all(|&n| n < 10) && !is10(&isbn) {
+        isbn[9] = checksum10(&isbn);
+    } else if isbn.len() == 13 && isbn[..12].iter().all(|&n| n < 10) && !is13(&isbn) {
+        isbn[12] = checksum13(&isbn);
+    } else {
+        return None;
+    }
+    Some(isbn_str(&isbn))
+}
+
+/// Validates an International Standard Music Number (979-0 or legacy "M" prefix).
+pub fn is_ismn(input: &str) -> bool {
+    let input = input.trim();
+    let ean = match input.strip_prefix(['M', 'm']) {
+        Some(rest) => digits(&format!("9790{rest}")),
+        None => digits(input),
+    };
+    ean.starts_with(&[9, 7, 9, 0]) && is13(&ean)
+}
+
+/// Validates an International Standard Serial Number.
+pub fn is_issn(input: &str) -> bool {
+    let issn = digits(input);
+    if issn.len() != 8 || issn[..7].iter().any(|&n| n >= 10) {
+        return false;
+    }
+    let sum = issn[..7]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, &n)| acc + (8 - i) * n as usize);
+    issn[7] as usize == (11 - sum % 11) % 11
+}
+
+/// Validates an EAN-13 barcode.
+pub fn is_ean13(input: &str) -> bool {
+    is13(&digits(input))
+}
+
+/// Classifies the code and returns its alternative forms.
+pub fn info(input: &str) -> Info {
+    let stripped = parse(input).unwrap_or_else(|invalid| invalid);
+    let code = if is_ismn(input) {
+        Code::Ismn
+    } else if is10(&digits(input)) {
+        Code::Isbn10
+    } else if to_isbn10(input).is_some() || digits(input).starts_with(&[9, 7, 9]) && is_ean13(input)
+    {
+        Code::Isbn13
+    } else if is_issn(input) {
+        Code::Issn
+    } else if is_ean13(input) {
+        Code::Ean
+    } else {
+        Code::Unknown
+    };
+    let is_isbn = matches!(code, Code::Isbn10 | Code::Isbn13);
+    Info {
+        code,
+        stripped,
+        isbn10: is_isbn
+            .then(|| to_isbn10(input))
+            .flatten()
+            .unwrap_or_default(),
+        isbn13: is_isbn
+            .then(|| to_isbn13(input))
+            .flatten()
+            .unwrap_or_default(),
+        hyphenated: is_isbn
+            .then(|| hyphenate(input))
+            .flatten()
+            .unwrap_or_default(),
+        repaired: repair(input).unwrap_or_default(),
+    }
+}
+
+fn digits(input: &str) -> Vec<u8> {
+    input
         .chars()
         .filter_map(|c| {
             if c == 'X' || c == 'x' {
@@ -12,13 +294,7 @@ pub fn parse(input: &str) -> Result<String, String> {
                 c.to_digit(10).map(|n| n as u8)
             }
         })
-        .collect();
-
-    if is10(&isbn) || is13(&isbn) {
-        Ok(isbn_str(&isbn))
-    } else {
-        Err(isbn_str(&isbn))
-    }
+        .collect()
 }
 
 fn is10(isbn: &[u8]) -> bool {
@@ -65,4 +341,62 @@ mod tests {
         assert_eq!(parse("353411292X"), Ok("353411292X".into()));
         assert_eq!(parse("35341129XX"), Err("35341129XX".into()));
     }
+
+    #[test]
+    fn convert() {
+        assert_eq!(to_isbn13("3-440-03914-5").unwrap(), "9783440039144");
+        assert_eq!(to_isbn13("9783440039144").unwrap(), "9783440039144");
+        assert_eq!(to_isbn10("978-3-440-03914-4").unwrap(), "3440039145");
+        assert_eq!(to_isbn10("978-0-261-10221-7").unwrap(), "0261102214");
+        assert_eq!(to_isbn10("979-10-90636-07-1"), None);
+        assert_eq!(to_isbn13("1234567890"), None);
+        assert!(same("353411292X", "9783534112920"));
+        assert!(!same("353411292X", "9783534112921"));
+    }
+
+    #[test]
+    fn hyphenation() {
+        assert_eq!(hyphenate("3440039145").unwrap(), "3-440-03914-5");
+        assert_eq!(hyphenate("353411292X").unwrap(), "3-534-11292-X");
+        assert_eq!(hyphenate("9783923923410").unwrap(), "978-3-923923-41-0");
+        assert_eq!(hyphenate("9783060016150").unwrap(), "978-3-06-001615-0");
+        assert_eq!(hyphenate("9781338099133").unwrap(), "978-1-338-09913-3");
+        assert_eq!(hyphenate("9780261102217").unwrap(), "978-0-261-10221-7");
+        // unsupported group (japan)
+        assert_eq!(hyphenate("9784062938426"), None);
+    }
+
+    #[test]
+    fn other_codes() {
+        assert!(is_issn("0317-8471"));
+        assert!(!is_issn("0317-8472"));
+        assert!(is_ismn("979-0-2600-0043-8"));
+        assert!(is_ismn("M-2600-0043-8"));
+        assert!(!is_ismn("9783440039144"));
+        assert!(is_ean13("4006381333931"));
+
+        assert_eq!(info("0317-8471").code, Code::Issn);
+        assert_eq!(info("M-2600-0043-8").code, Code::Ismn);
+        assert_eq!(info("4006381333931").code, Code::Ean);
+        assert_eq!(
+            info("3-440-03914-5"),
+            Info {
+                code: Code::Isbn10,
+                stripped: "3440039145".into(),
+                isbn10: "3440039145".into(),
+                isbn13: "9783440039144".into(),
+                hyphenated: "3-440-03914-5".into(),
+                repaired: String::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn repair_check_digit() {
+        assert_eq!(repair("3-440-03914-4").unwrap(), "3440039145");
+        assert_eq!(repair("3534112921").unwrap(), "353411292X");
+        assert_eq!(repair("9783440039140").unwrap(), "9783440039144");
+        assert_eq!(repair("9783440039144"), None);
+        assert_eq!(repair("12345"), None);
+    }
 }
@@ -0,0 +1,352 @@
+use std::fmt::Write;
+
+use serde::Deserialize;
+
+use crate::db::{Book, User};
+use crate::provider::marc::escape;
+
+/// Width of the A4 pages in mm
+const PAGE_WIDTH: f64 = 210.0;
+/// Height of the A4 pages in mm
+const PAGE_HEIGHT: f64 = 297.0;
+/// Inner margin of the labels in mm
+const PADDING: f64 = 1.5;
+/// Empty space before and after the barcode in modules
+const QUIET_ZONE: usize = 10;
+
+/// Label sheet formats for A4 pages
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Layout {
+    /// Avery Zweckform L4736: 48 labels with 45.7 x 21.2 mm
+    #[default]
+    L4736,
+    /// Avery Zweckform L7651: 65 labels with 38.1 x 21.2 mm
+    L7651,
+    /// Avery Zweckform 3474: 24 labels with 70 x 37 mm
+    Z3474,
+    /// Avery Zweckform C32010: 10 business cards with 85 x 54 mm
+    C32010,
+}
+
+/// Dimensions of a label sheet in mm
+struct Sheet {
+    columns: usize,
+    rows: usize,
+    width: f64,
+    height: f64,
+    left: f64,
+    top: f64,
+    column_gap: f64,
+    row_gap: f64,
+}
+
+impl Layout {
+    fn sheet(self) -> Sheet {
+        match self {
+            Layout::L4736 => Sheet {
+                columns: 4,
+                rows: 12,
+                width: 45.7,
+                height: 21.2,
+                left: 9.75,
+                top: 21.5,
+                column_gap: 2.5,
+                row_gap: 0.0,
+            },
+            Layout::L7651 => Sheet {
+                columns: 5,
+                rows: 13,
+                width: 38.1,
+                height: 21.2,
+                left: 4.75,
+                top: 10.7,
+                column_gap: 2.5,
+                row_gap: 0.0,
+            },
+            Layout::Z3474 => Sheet {
+                columns: 3,
+                rows: 8,
+                width: 70.0,
+                height: 37.0,
+                left: 0.0,
+                top: 0.5,
+                column_gap: 0.0,
+                row_gap: 0.0,
+            },
+            Layout::C32010 => Sheet {
+                columns: 2,
+                rows: 5,
+                width: 85.0,
+                height: 54.0,
+                left: 15.0,
+                top: 13.5,
+                column_gap: 10.0,
+                row_gap: 0.0,
+            },
+        }
+    }
+}
+
+/// Content of a single label
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    /// Header line, e.g., the book title
+    pub title: String,
+    /// Encoded as barcode and printed below it
+    pub code: String,
+    /// Printed in the bottom right corner, e.g., the category
+    pub note: String,
+}
+
+impl From<&Book> for Label {
+    fn from(book: &Book) -> Self {
+        Self {
+            title: book.title.clone(),
+            code: book.id.clone(),
+            note: book.category.clone(),
+        }
+    }
+}
+
+impl From<&User> for Label {
+    fn from(user: &User) -> Self {
+        Self {
+            title: format!("{} {}", user.forename, user.surname),
+            code: user.account.clone(),
+            note: user.role.clone(),
+        }
+    }
+}
+
+/// Renders the labels as printable HTML document with one SVG per page.
+///
+/// The first `skip` labels of the first sheet are left empty, to reuse
+/// partially used sheets.
+/// Labels with codes that cannot be encoded are skipped and listed on screen.
+pub fn html(layout: Layout, skip: usize, labels: &[Label]) -> String {
+    let sheet = layout.sheet();
+    let per_page = sheet.columns * sheet.rows;
+    let skip = skip % per_page;
+    let (labels, skipped): (Vec<_>, Vec<_>) = labels.iter().partition(|l| encodable(&l.code));
+
+    let mut html = String::from(concat!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Labels</title>",
+        "<style>@page{size:A4;margin:0}body{margin:0}",
+        "svg{display:block;break-after:page}",
+        ".skipped{margin:1em;color:red}@media print{.skipped{display:none}}",
+        "</style></head><body>"
+    ));
+    if !skipped.is_empty() {
+        let codes = skipped.iter().map(|l| escape(&l.code)).collect::<Vec<_>>();
+        write!(
+            html,
+            "<p class=\"skipped\">Skipped, as the barcodes can only contain ASCII characters: {}</p>",
+            codes.join(", ")
+        )
+        .unwrap();
+    }
+    let slots = (0..skip).map(|_| None).chain(labels.into_iter().map(Some));
+    let slots = slots.collect::<Vec<_>>();
+    for page in slots.chunks(per_page) {
+        write!(
+            html,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{PAGE_WIDTH}mm\" \
+             height=\"{PAGE_HEIGHT}mm\" viewBox=\"0 0 {PAGE_WIDTH} {PAGE_HEIGHT}\" \
+             font-family=\"sans-serif\">"
+        )
+        .unwrap();
+        for (i, label) in page.iter().enumerate() {
+            if let Some(label) = label {
+                let x = sheet.left + (i % sheet.columns) as f64 * (sheet.width + sheet.column_gap);
+                let y = sheet.top + (i / sheet.columns) as f64 * (sheet.height + sheet.row_gap);
+                label_svg(&mut html, x, y, sheet.width, sheet.height, label);
+            }
+        }
+        html.push_str("</svg>");
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+/// Title on top, barcode in the middle, code and note on the bottom.
+fn label_svg(out: &mut String, x: f64, y: f64, width: f64, height: f64, label: &Label) {
+    let font = (height * 0.13).min(3.5);
+    let inner = width - 2.0 * PADDING;
+    let (left, right) = (x + PADDING, x + width - PADDING);
+
+    let title = truncate(&label.title, inner, font);
+    let baseline = y + PADDING + font;
+    write!(
+        out,
+        "<text x=\"{left:.2}\" y=\"{baseline:.2}\" font-size=\"{font:.2}\" \
+         font-weight=\"bold\">{}</text>",
+        escape(&title)
+    )
+    .unwrap();
+
+    let top = baseline + font * 0.4;
+    let bottom = y + height - PADDING - font * 1.3;
+    if bottom > top {
+        barcode_svg(out, left, top, inner, bottom - top, &label.code);
+    }
+
+    let baseline = y + height - PADDING;
+    write!(
+        out,
+        "<text x=\"{left:.2}\" y=\"{baseline:.2}\" font-size=\"{font:.2}\">{}</text>\
+         <text x=\"{right:.2}\" y=\"{baseline:.2}\" font-size=\"{font:.2}\" \
+         text-anchor=\"end\">{}</text>",
+        escape(&label.code),
+        escape(&label.note)
+    )
+    .unwrap();
+}
+
+fn barcode_svg(out: &mut String, x: f64, y: f64, width: f64, height: f64, code: &str) {
+    let Some(bars) = code128(code) else {
+        return;
+    };
+    let modules = bars.iter().map(|&b| b as usize).sum::<usize>() + 2 * QUIET_ZONE;
+    let module = width / modules as f64;
+    let mut pos = x + QUIET_ZONE as f64 * module;
+    for (i, &bar) in bars.iter().enumerate() {
+        let w = bar as f64 * module;
+        if i % 2 == 0 {
+            write!(
+                out,
+                "<rect x=\"{pos:.3}\" y=\"{y:.2}\" width=\"{w:.3}\" height=\"{height:.2}\"/>"
+            )
+            .unwrap();
+        }
+        pos += w;
+    }
+}
+
+/// Shortens the text to fit approximately into the width.
+fn truncate(text: &str, width: f64, font: f64) -> String {
+    let max = (width / (font * 0.55)) as usize;
+    if text.chars().count() <= max {
+        text.into()
+    } else {
+        let mut text = text.chars().take(max.saturating_sub(1)).collect::<String>();
+        text.push('…');
+        text
+    }
+}
+
+/// Bar and space widths of the Code 128 symbols
+const CODE128: [&str; 107] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212",
+    "221213", "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221",
+    "223211", "221132", "221231", "213212", "223112", "312131", "311222", "321122", "321221",
+    "312212", "322112", "322211", "212123", "212321", "232121", "111323", "131123", "131321",
+    "112313", "132113", "132311", "211313", "231113", "231311", "112133", "112331", "132131",
+    "113123", "113321", "133121", "313121", "211331", "231131", "213113", "213311", "213131",
+    "311123", "311321", "331121", "312113", "312311", "332111", "314111", "221411", "431111",
+    "111224", "111422", "121124", "121421", "141122", "141221", "112214", "112412", "122114",
+    "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111", "111242",
+    "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311",
+    "113141", "114131", "311141", "411131", "211412", "211214", "211232", "2331112",
+];
+const CODE128_START_B: usize = 104;
+const CODE128_STOP: usize = 106;
+
+/// Whether the code can be encoded as barcode, which is limited to printable ASCII.
+pub fn encodable(code: &str) -> bool {
+    code.chars().all(|c| (' '..='~').contains(&c))
+}
+
+/// Encodes the text with the Code 128 code set B.
+///
+/// Returns the alternating bar and space widths in modules,
+/// or none if the text is not [`encodable`].
+fn code128(text: &str) -> Option<Vec<u8>> {
+    if !encodable(text) {
+        return None;
+    }
+    let mut symbols = vec![CODE128_START_B];
+    for c in text.chars() {
+        symbols.push(c as usize - ' ' as usize);
+    }
+    let checksum = symbols
+        .iter()
+        .enumerate()
+        .map(|(i, &s)| i.max(1) * s)
+        .sum::<usize>()
+        % 103;
+    symbols.push(checksum);
+    symbols.push(CODE128_STOP);
+
+    let bars = symbols
+        .into_iter()
+        .flat_map(|s| CODE128[s].bytes().map(|b| b - b'0'))
+        .collect();
+    Some(bars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code128_table() {
+        for (i, symbol) in CODE128.iter().enumerate() {
+            let modules = symbol.bytes().map(|b| (b - b'0') as usize).sum::<usize>();
+            assert_eq!(modules, if i == CODE128_STOP { 13 } else { 11 }, "{i}");
+            assert!(!CODE128[..i].contains(symbol), "{i}");
+        }
+    }
+
+    #[test]
+    fn code128_encoding() {
+        // start B, 'A' = 33, checksum (104 + 33) % 103 = 34, stop
+        let bars = code128("A").unwrap();
+        let expected = ["211214", "111323", "131123", "2331112"].concat();
+        let expected = expected.bytes().map(|b| b - b'0').collect::<Vec<_>>();
+        assert_eq!(bars, expected);
+        // start + 11 symbols + checksum + stop
+        assert_eq!(
+            code128("FANT TOLK 1")
+                .unwrap()
+                .iter()
+                .map(|&b| b as usize)
+                .sum::<usize>(),
+            11 * 13 + 13
+        );
+        assert_eq!(code128("FANT BÄUM 1"), None);
+    }
+
+    #[test]
+    fn sheets() {
+        for layout in [Layout::L4736, Layout::L7651, Layout::Z3474, Layout::C32010] {
+            let s = layout.sheet();
+            let width =
+                2.0 * s.left + s.columns as f64 * s.width + (s.columns - 1) as f64 * s.column_gap;
+            let height = 2.0 * s.top + s.rows as f64 * s.height + (s.rows - 1) as f64 * s.row_gap;
+            assert!((width - PAGE_WIDTH).abs() < 0.5, "{layout:?} {width}");
+            assert!((height - PAGE_HEIGHT).abs() < 0.5, "{layout:?} {height}");
+        }
+    }
+
+    #[test]
+    fn pages() {
+        let label = Label {
+            title: "Der Hobbit <oder> Hin und zurück".into(),
+            code: "FANT TOLK 1".into(),
+            note: "FANT".into(),
+        };
+        let mut labels = std::iter::repeat_n(label, 8).collect::<Vec<_>>();
+        labels.push(Label {
+            title: "Nathan der Weise".into(),
+            code: "LESSING Ä".into(),
+            note: String::new(),
+        });
+        let html = html(Layout::C32010, 3, &labels);
+        assert_eq!(html.matches("<svg").count(), 2);
+        assert_eq!(html.matches("FANT TOLK 1</text>").count(), 8);
+        assert!(html.contains("&lt;oder&gt;"));
+        assert!(!html.contains("Nathan"));
+        assert!(html.contains("ASCII characters: LESSING Ä</p>"));
+    }
+}
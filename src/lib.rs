@@ -1,7 +1,9 @@
 pub mod db;
 pub mod error;
+pub mod export;
 pub mod fuzzy;
 pub mod isbn;
+pub mod labels;
 pub mod mail;
 pub mod provider;
 pub mod server;
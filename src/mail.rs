@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+use chrono::NaiveDate;
 use email_address::EmailAddress;
 use lettre::message::header::ContentType;
 use lettre::message::{Mailbox, SinglePartBuilder};
@@ -8,6 +9,7 @@ use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executo
 use tracing::{error, info};
 use unicode_normalization::UnicodeNormalization;
 
+use crate::db::{MailTemplate, Overdue, Settings};
 use crate::error::{Error, Result};
 
 /// Checks if the username is valid for an email
@@ -15,6 +17,36 @@ pub fn account_is_valid(account: &str) -> bool {
     EmailAddress::is_valid_local_part(account)
 }
 
+/// Fills the reminder template for the borrower of the overdue book.
+///
+/// The stronger reminder is used if the deadline has passed for more than the warning delay.
+/// Returns the account of the borrower and the mail.
+pub fn reminder(
+    settings: &Settings,
+    overdue: &Overdue,
+    today: NaiveDate,
+) -> Option<(String, MailTemplate)> {
+    let borrower = overdue.book.borrower.as_ref()?;
+    let template = if (today - borrower.deadline).num_days() > settings.overdue_warning_delay as i64
+    {
+        &settings.mail_overdue2
+    } else {
+        &settings.mail_overdue
+    };
+    let username = format!("{} {}", overdue.user.forename, overdue.user.surname);
+    let fill = |text: &str| {
+        text.replace("{booktitle}", &overdue.book.title)
+            .replace("{username}", &username)
+    };
+    Some((
+        borrower.user.clone(),
+        MailTemplate {
+            subject: fill(&template.subject),
+            body: fill(&template.body),
+        },
+    ))
+}
+
 pub async fn send(
     host: &str,
     password: &str,
@@ -77,6 +109,60 @@ impl From<lettre::transport::smtp::Error> for Error {
 
 #[cfg(test)]
 mod tests {
+    use chrono::NaiveDate;
+
+    use crate::db::{Book, Borrower, MailTemplate, Overdue, Settings, User};
+
+    #[test]
+    fn reminder() {
+        let settings = Settings {
+            overdue_warning_delay: 14,
+            mail_overdue: MailTemplate {
+                subject: "Reminder: {booktitle}".into(),
+                body: "Dear {username}, please return {booktitle}.".into(),
+            },
+            mail_overdue2: MailTemplate {
+                subject: "Last reminder: {booktitle}".into(),
+                body: "{username}!".into(),
+            },
+            ..Default::default()
+        };
+        let deadline = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let overdue = Overdue {
+            book: Book {
+                title: "Eragon".into(),
+                borrower: Some(Borrower {
+                    user: "foo.bar".into(),
+                    deadline,
+                }),
+                ..Default::default()
+            },
+            user: User {
+                account: "foo.bar".into(),
+                forename: "Foo".into(),
+                surname: "Bar".into(),
+                ..Default::default()
+            },
+        };
+
+        let (account, mail) =
+            super::reminder(&settings, &overdue, deadline + chrono::Days::new(14)).unwrap();
+        assert_eq!(account, "foo.bar");
+        assert_eq!(mail.subject, "Reminder: Eragon");
+        assert_eq!(mail.body, "Dear Foo Bar, please return Eragon.");
+
+        let (_, mail) =
+            super::reminder(&settings, &overdue, deadline + chrono::Days::new(15)).unwrap();
+        assert_eq!(mail.subject, "Last reminder: Eragon");
+        assert_eq!(mail.body, "Foo Bar!");
+
+        let returned = Overdue {
+            book: Book::default(),
+            user: overdue.user.clone(),
+        };
+        assert!(super::reminder(&settings, &returned, deadline).is_none());
+    }
+
     #[tokio::test]
     #[ignore]
     async fn send_mail() {
@@ -1,16 +1,27 @@
-use std::fs::File;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-use clap::Parser;
+use chrono::Local;
+use clap::{Args, Parser, Subcommand};
+use tracing::error;
 
-use crate::db::AtomicDatabase;
-use crate::server::{Tls, UserConfig};
+use crate::db::{AtomicDatabase, BackupKind, Backups, Database, StorageKind, migrate};
+use crate::error::{Error, Result};
+use crate::export::{Columns, Format};
+use crate::mail::account_is_valid;
+use crate::provider::marc::{self, MarcFormat};
+use crate::server::{Library, LibraryConfig, Tls, UserConfig};
 
 mod db;
 mod error;
+mod export;
 mod fuzzy;
 mod isbn;
+mod labels;
 mod mail;
 mod provider;
 mod server;
@@ -18,21 +29,94 @@ mod util;
 
 /// Schiller Library Backend
 #[derive(Parser)]
-struct Args {
+struct Cli {
+    #[command(flatten)]
+    options: Options,
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Database options, shared by all commands
+#[derive(Args)]
+struct Options {
+    /// Path to the database
+    #[arg(short, long, default_value = "lib.json", global = true)]
+    db: PathBuf,
+    /// How changes to the database are saved
+    #[arg(long, value_enum, default_value_t, global = true)]
+    storage: StorageKind,
+    /// Continue with the database of a write that was interrupted by a crash
+    #[arg(long, global = true)]
+    recover: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start the webserver
+    Serve(Serve),
+    /// Import books from a MARC21-xml or ISO 2709 file
+    Import {
+        file: PathBuf,
+        /// Category of the imported books
+        #[arg(short, long)]
+        category: String,
+    },
+    /// Export the library data
+    #[command(subcommand)]
+    Export(Export),
+    /// Migrate the database to the current version
+    Migrate {
+        /// Only report what would change, without modifying the database
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Take a snapshot of the database
+    Backup {
+        /// List the snapshots instead, the newest first
+        #[arg(long)]
+        list: bool,
+    },
+    /// Replace the database with a snapshot
+    Restore {
+        /// Name of the snapshot, see `backup --list`
+        name: String,
+    },
+    /// Validate the database and report integrity problems with suggested fixes
+    Check {
+        /// Repair the problems that can be fixed automatically
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Manage the users
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Send the reminder mails for the overdue books
+    ///
+    /// The date of the reminders is stored in the database,
+    /// which requires the server to be stopped.
+    SendReminders {
+        /// Send them, even if they were already sent today
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Args)]
+struct Serve {
     /// Ip and port for the webserver
     host: SocketAddr,
     /// Externally visible domain of this webserver
     #[arg(long)]
     domain: Option<String>,
+    /// Path to the list of libraries, that are served instead of the single database
+    #[arg(long, conflicts_with_all = ["auth", "user_file"])]
+    libraries: Option<PathBuf>,
     /// Path to the oauth config
     #[arg(long)]
     auth: Option<PathBuf>,
     /// Directory for the static assets
     #[arg(short, long, default_value = "lib-view/build")]
     assets: PathBuf,
-    /// Path to the database
-    #[arg(short, long, default_value = "lib.json")]
-    db: PathBuf,
     /// Path to the users file
     #[arg(long)]
     user_file: Option<PathBuf>,
@@ -47,41 +131,333 @@ struct Args {
     key: PathBuf,
 }
 
+#[derive(Subcommand)]
+enum Export {
+    /// All books
+    Books(Table),
+    /// All users
+    Users(Table),
+    /// Expired borrowing periods
+    Overdues(Table),
+    /// Number and value of the books per category
+    Inventory(Table),
+    /// Books that are suggested for removal
+    Weeding(Table),
+    /// All books as MARC records
+    Marc {
+        #[arg(short, long, value_enum, default_value_t)]
+        format: MarcFormat,
+        /// Output file, instead of the standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct Table {
+    #[arg(short, long, value_enum, default_value_t)]
+    format: Format,
+    /// Comma separated list of columns, all if empty
+    #[arg(short, long, default_value = "")]
+    columns: String,
+    /// Output file, instead of the standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Update the roles of all users from the users file
+    ///
+    /// Requires the server to be stopped.
+    Sync {
+        /// Path to the users file
+        #[arg(long)]
+        user_file: PathBuf,
+        /// CSV row delimiter for the users file
+        #[arg(long, default_value_t = ',')]
+        user_delimiter: char,
+    },
+}
+
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     util::logging();
 
-    let Args {
+    let Cli { options, command } = Cli::parse();
+    match run(options, command).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("Command failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(options: Options, command: Command) -> Result<()> {
+    match command {
+        Command::Serve(serve) => {
+            start(options, serve).await;
+            Ok(())
+        }
+        Command::Import { file, category } => {
+            let records = marc::parse_file(&fs::read(file)?)?;
+            let db = options.open(None)?;
+            db.backup(BackupKind::Import)?;
+            let books = db.transaction(|db| db.import(records, category.trim()))?;
+            println!("Imported {} books", books.len());
+            Ok(())
+        }
+        Command::Export(export) => {
+            let db = options.read()?;
+            match export {
+                Export::Books(table) => {
+                    table.write(&db.books.data.values().cloned().collect::<Vec<_>>())
+                }
+                Export::Users(table) => {
+                    table.write(&db.users.data.values().cloned().collect::<Vec<_>>())
+                }
+                Export::Overdues(table) => table.write(&db.overdues()?),
+                Export::Inventory(table) => table.write(&db.inventory()?),
+                Export::Weeding(table) => table.write(&db.weeding()),
+                Export::Marc { format, output } => {
+                    let books = db.books.data.values().cloned().collect::<Vec<_>>();
+                    marc::write(writer(output.as_deref())?, format, &books)
+                }
+            }
+        }
+        Command::Migrate { dry_run } => {
+            let steps = migrate::dry_run(&options.db)?;
+            for step in &steps {
+                println!(
+                    "{}: {} ({} changes)",
+                    step.version, step.description, step.changes
+                );
+            }
+            if steps.is_empty() {
+                println!("No migrations necessary");
+            }
+            if !dry_run {
+                // migrated and saved on load
+                drop(options.open(None)?);
+            }
+            Ok(())
+        }
+        Command::Backup { list } => {
+            let backups = Backups::new(&options.db.with_extension("json"));
+            if list {
+                for backup in backups.list()? {
+                    println!("{}\t{:?}\t{} bytes", backup.name, backup.kind, backup.size);
+                }
+            } else {
+                let backup = backups.create(&options.read()?, BackupKind::Manual)?;
+                println!("Created {}", backup.name);
+            }
+            Ok(())
+        }
+        Command::Restore { name } => options.open(None)?.restore(&name),
+        Command::Check { repair } => {
+            if repair {
+                let db = options.open(None)?;
+                db.backup(BackupKind::Repair)?;
+                for issue in db.transaction(|db| Ok(db.repair()))? {
+                    println!("Repaired {issue}");
+                }
+            }
+            let db = options.read()?;
+            let issues = db.check();
+            for issue in &issues {
+                println!("{issue}");
+            }
+            let stats = db.stats()?;
+            println!(
+                "{} books, {} users, {} categories, {} borrowed, {} reserved, {} overdue",
+                stats.books,
+                stats.users,
+                stats.categories,
+                stats.borrows,
+                stats.reservations,
+                stats.overdues
+            );
+            if issues.is_empty() {
+                Ok(())
+            } else {
+                let repairable = issues.iter().filter(|i| i.repairable()).count();
+                error!(
+                    "Found {} problems, {repairable} can be repaired with '--repair'",
+                    issues.len()
+                );
+                Err(Error::InvalidFormat)
+            }
+        }
+        Command::User(UserCommand::Sync {
+            user_file,
+            user_delimiter,
+        }) => {
+            let user = user_config(Some(user_file), user_delimiter)?.unwrap();
+            let roles = provider::user::load_roles(&user.file, user.delimiter)?;
+            let db = options.open(Some(&user))?;
+            db.backup(BackupKind::Import)?;
+            db.transaction(|db| db.users.update_roles(roles.into_iter()))
+        }
+        Command::SendReminders { force } => send_reminders(options.open(None)?, force).await,
+    }
+}
+
+impl Options {
+    /// Lock and load the database, to modify it.
+    fn open(&self, user: Option<&UserConfig>) -> Result<AtomicDatabase> {
+        AtomicDatabase::load(&self.db, user, self.storage, self.recover)
+    }
+    /// Load the database for reading, this also works while the server is running.
+    fn read(&self) -> Result<Database> {
+        Database::open(&self.db)
+    }
+}
+
+impl Table {
+    fn write<T: Columns>(self, records: &[T]) -> Result<()> {
+        let columns = export::columns::<T>(&self.columns)?;
+        export::write(
+            writer(self.output.as_deref())?,
+            self.format,
+            &columns,
+            records,
+        )
+    }
+}
+
+/// Output file or the standard output.
+fn writer(output: Option<&Path>) -> Result<Box<dyn Write>> {
+    Ok(match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    })
+}
+
+fn user_config(user_file: Option<PathBuf>, delimiter: char) -> Result<Option<UserConfig>> {
+    if !delimiter.is_ascii() {
+        error!("The delimiter has to be an ascii character");
+        return Err(Error::Arguments);
+    }
+    if let Some(file) = &user_file
+        && !file.exists()
+    {
+        error!("User file not found: {file:?}");
+        return Err(Error::FileOpen);
+    }
+    Ok(user_file.map(|file| UserConfig {
+        file,
+        delimiter: delimiter as u8,
+    }))
+}
+
+async fn start(options: Options, serve: Serve) {
+    let Serve {
         host,
         domain,
+        libraries,
         auth,
         assets,
-        db,
         user_file,
         user_delimiter,
         cert,
         key,
-    } = Args::parse();
+    } = serve;
 
-    let auth = auth.map(|auth| {
+    let load_auth = |auth: PathBuf| {
         serde_json::from_reader(File::open(auth).expect("No OAuth Config found")).unwrap()
-    });
-
-    assert!(user_delimiter.is_ascii());
-    let delimiter = user_delimiter as u8;
-    if let Some(user_file) = &user_file {
-        assert!(user_file.exists(), "User file not found: {user_file:?}");
-    }
-    let user = user_file.map(|file| UserConfig { file, delimiter });
+    };
+    let load_db = |path: &Path, user: Option<&UserConfig>| {
+        if path.exists() {
+            AtomicDatabase::load(path, user, options.storage, options.recover).unwrap()
+        } else {
+            AtomicDatabase::create(path, options.storage).unwrap()
+        }
+    };
 
-    let domain = domain.unwrap_or_else(|| host.to_string());
+    let libraries = if let Some(libraries) = libraries {
+        let configs: Vec<LibraryConfig> =
+            serde_json::from_reader(File::open(libraries).expect("No library config found"))
+                .unwrap();
+        assert!(!configs.is_empty(), "No libraries configured");
+        let mut names = HashSet::new();
+        let mut dbs = HashSet::new();
+        for config in &configs {
+            assert!(
+                names.insert(&config.name),
+                "Duplicate library {}",
+                config.name
+            );
+            assert!(dbs.insert(&config.db), "Duplicate database {:?}", config.db);
+        }
 
-    let db = if db.exists() {
-        AtomicDatabase::load(&db, user.as_ref()).unwrap()
+        configs
+            .into_iter()
+            .map(|config| {
+                let user = user_config(config.user_file, config.user_delimiter).unwrap();
+                Library {
+                    db: load_db(&config.db, user.as_ref()),
+                    name: config.name,
+                    domain: config.domain,
+                    auth: config.auth.map(load_auth),
+                    user,
+                }
+            })
+            .collect()
     } else {
-        AtomicDatabase::create(&db).unwrap()
+        let user = user_config(user_file, user_delimiter).unwrap();
+        vec![Library {
+            name: "lib".into(),
+            domain: None,
+            db: load_db(&options.db, user.as_ref()),
+            auth: auth.map(load_auth),
+            user,
+        }]
     };
 
+    let domain = domain.unwrap_or_else(|| host.to_string());
     let tls = Tls { cert, key };
-    server::start(host, &domain, db, assets, tls, auth, user).await;
+    server::start(host, &domain, libraries, assets, tls).await;
+}
+
+/// Sends the reminders for all overdue books, once per day.
+async fn send_reminders(db: AtomicDatabase, force: bool) -> Result<()> {
+    let today = Local::now().date_naive();
+    let (settings, overdues) = {
+        let db = db.read();
+        (db.mail_settings().clone(), db.overdues()?)
+    };
+    if settings.mail_last_reminder >= today && !force {
+        println!("The reminders were already sent today");
+        return Ok(());
+    }
+    if !account_is_valid(&settings.mail_from) {
+        error!("Invalid sender {}", settings.mail_from);
+        return Err(Error::Arguments);
+    }
+
+    let mut sent = 0;
+    for overdue in &overdues {
+        if let Some((account, mail)) = mail::reminder(&settings, overdue, today) {
+            mail::send(
+                &settings.mail_host,
+                &settings.mail_password,
+                &settings.mail_from,
+                &account,
+                &mail.subject,
+                &mail.body,
+            )
+            .await?;
+            sent += 1;
+        }
+    }
+
+    db.transaction(|db| {
+        let mut settings = db.settings();
+        settings.mail_last_reminder = today;
+        db.settings_update(settings)
+    })?;
+    println!("Sent {sent} reminders");
+    Ok(())
 }
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{BookData, Provider};
+use crate::error::{Error, Result};
+use crate::isbn;
+
+/// Duration a found book is cached
+const TTL: TimeDelta = TimeDelta::days(30);
+/// Duration a book that was not found is cached
+const NEGATIVE_TTL: TimeDelta = TimeDelta::days(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    fetched: DateTime<Utc>,
+    /// `None` if no provider found the book
+    data: Option<BookData>,
+}
+
+impl Entry {
+    fn is_valid(&self, now: DateTime<Utc>) -> bool {
+        let ttl = if self.data.is_some() {
+            TTL
+        } else {
+            NEGATIVE_TTL
+        };
+        now - self.fetched < ttl
+    }
+}
+
+/// Persistent cache for the provider results, keyed by the normalized isbn.
+///
+/// Network errors are not cached.
+#[derive(Debug)]
+pub struct Cache {
+    path: PathBuf,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    /// Serializes the writes of the cache file
+    saving: Arc<Mutex<()>>,
+}
+
+impl Cache {
+    /// Load the cache file, starting empty if it is missing or invalid.
+    pub fn load(path: &Path) -> Self {
+        let entries = match File::open(path) {
+            Ok(file) => serde_json::from_reader(file).unwrap_or_else(|e| {
+                warn!("Discarding invalid cache {path:?}: {e}");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path: path.into(),
+            entries: Arc::new(Mutex::new(entries)),
+            saving: Arc::default(),
+        }
+    }
+
+    /// Remove all entries, e.g., if the providers have changed.
+    pub async fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+        self.save().await;
+    }
+
+    /// Fetch the book data from the cache or the providers.
+    pub async fn fetch(
+        &self,
+        client: &Client,
+        providers: &[Provider],
+        isbn: &str,
+    ) -> Result<BookData> {
+        let (result, inserted) = self.fetch_unsaved(client, providers, &key(isbn)?).await;
+        if inserted {
+            self.save().await;
+        }
+        result
+    }
+
+    /// Fetch all uncached books one after another, to warm up the cache.
+    ///
+    /// Failures are only logged.
+    pub async fn prefetch(&self, client: &Client, providers: &[Provider], isbns: Vec<String>) {
+        info!("Prefetch {} books", isbns.len());
+        let mut changed = false;
+        for isbn in isbns {
+            let Ok(key) = key(&isbn) else {
+                continue;
+            };
+            let (result, inserted) = self.fetch_unsaved(client, providers, &key).await;
+            if let Err(e) = result {
+                warn!("Prefetch failed for {isbn}: {e:?}");
+            }
+            changed |= inserted;
+        }
+        if changed {
+            self.save().await;
+        }
+    }
+
+    /// Returns the normalized isbns that are not cached.
+    pub fn missing(&self, isbns: &[String]) -> Vec<String> {
+        let mut missing = Vec::new();
+        for isbn in isbns {
+            if let Ok(key) = key(isbn)
+                && self.get(&key).is_none()
+                && !missing.contains(&key)
+            {
+                missing.push(key);
+            }
+        }
+        missing
+    }
+
+    fn get(&self, key: &str) -> Option<Result<BookData>> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key).filter(|e| e.is_valid(Utc::now()))?;
+        Some(entry.data.clone().ok_or(Error::NothingFound))
+    }
+
+    fn insert(&self, key: String, data: Option<BookData>) {
+        let entry = Entry {
+            fetched: Utc::now(),
+            data,
+        };
+        self.entries.lock().unwrap().insert(key, entry);
+    }
+
+    /// Also returns whether a new entry was inserted.
+    async fn fetch_unsaved(
+        &self,
+        client: &Client,
+        providers: &[Provider],
+        key: &str,
+    ) -> (Result<BookData>, bool) {
+        if let Some(result) = self.get(key) {
+            return (result, false);
+        }
+        match super::fetch(client, providers, key).await {
+            Ok(data) => {
+                self.insert(key.into(), Some(data.clone()));
+                (Ok(data), true)
+            }
+            Err(Error::NothingFound) => {
+                self.insert(key.into(), None);
+                (Err(Error::NothingFound), true)
+            }
+            Err(e) => (Err(e), false),
+        }
+    }
+
+    /// Write the valid entries to the cache file, without blocking the runtime.
+    async fn save(&self) {
+        let path = self.path.clone();
+        let entries = self.entries.clone();
+        let saving = self.saving.clone();
+        let result = tokio::task::spawn_blocking(move || -> Result<()> {
+            // snapshots are written in order
+            let _saving = saving.lock().unwrap();
+            let snapshot = {
+                let mut entries = entries.lock().unwrap();
+                let now = Utc::now();
+                entries.retain(|_, e| e.is_valid(now));
+                entries.clone()
+            };
+
+            let tmp = path.with_extension("tmp");
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            serde_json::to_writer(&mut writer, &snapshot)?;
+            writer.flush()?;
+            fs::rename(tmp, &path)?;
+            Ok(())
+        })
+        .await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed saving the cache {:?}: {e:?}", self.path),
+            Err(e) => warn!("Failed saving the cache {:?}: {e}", self.path),
+        }
+    }
+}
+
+/// Normalize the isbn to the isbn-13 form, invalid checksums are tolerated.
+fn key(isbn: &str) -> Result<String> {
+    let key = isbn::to_isbn13(isbn)
+        .unwrap_or_else(|| isbn::parse(isbn).unwrap_or_else(|invalid| invalid));
+    if key.is_empty() {
+        return Err(Error::Arguments);
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn entry_ttl() {
+        let now = Utc::now();
+        let found = Entry {
+            fetched: now - TimeDelta::days(2),
+            data: Some(BookData::default()),
+        };
+        assert!(found.is_valid(now));
+        assert!(!found.is_valid(now + TTL));
+        let nothing = Entry {
+            fetched: now - TimeDelta::days(2),
+            data: None,
+        };
+        assert!(!nothing.is_valid(now));
+    }
+
+    #[tokio::test]
+    async fn persist() {
+        let path = env::temp_dir().join(format!("schiller-cache-{}.json", std::process::id()));
+        let cache = Cache::load(&path);
+        let data = BookData {
+            title: "Der Hobbit".into(),
+            ..BookData::default()
+        };
+        cache.insert("9783608939774".into(), Some(data));
+        cache.insert("3440040585".into(), None);
+        cache.save().await;
+
+        let cache = Cache::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            cache.get("9783608939774").unwrap().unwrap().title,
+            "Der Hobbit"
+        );
+        assert!(matches!(
+            cache.get("3440040585"),
+            Some(Err(Error::NothingFound))
+        ));
+        assert_eq!(
+            cache.missing(&["978-3-608-93977-4".into(), "3-570-30333-0".into()]),
+            ["9783570303337"]
+        );
+    }
+
+    #[tokio::test]
+    async fn hits_are_not_saved() {
+        let path = env::temp_dir().join(format!("schiller-cache-hit-{}.json", std::process::id()));
+        let cache = Cache::load(&path);
+        cache.insert("9783608939774".into(), Some(BookData::default()));
+        let data = cache.fetch(&Client::new(), &[], "978-3-608-93977-4").await;
+        assert!(data.is_ok());
+        assert!(!path.exists());
+    }
+}
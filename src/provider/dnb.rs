@@ -1,21 +1,19 @@
+use std::cmp::Reverse;
+
 use gluer::metadata;
+use reqwest::header::CONTENT_TYPE;
 use reqwest::{Client, Url};
-use serde::Serialize;
+use serde::Deserialize;
 use tracing::info;
-use unicode_normalization::UnicodeNormalization;
 
+use super::BookData;
+use super::marc::{Record, parse_single};
 use crate::error::{Error, Result};
+use crate::fuzzy::Fuzzy;
 
-#[metadata]
-#[derive(Debug, Default, PartialEq, Serialize)]
-pub struct BookData {
-    pub title: String,
-    pub authors: Vec<String>,
-    pub publisher: String,
-    pub costs: f64,
-}
 const URL: &str =
     "https://services.dnb.de/sru/dnb?version=1.1&operation=searchRetrieve&recordSchema=MARC21-xml";
+const COVER_URL: &str = "https://portal.dnb.de/opac/mvb/cover?isbn=";
 
 /// Try fetching the book data from the "Deutsche Nationalbibliothek"
 pub async fn fetch(client: &Client, isbn: &str) -> Result<BookData> {
@@ -27,16 +25,98 @@ pub async fn fetch(client: &Client, isbn: &str) -> Result<BookData> {
         return Err(Error::Network);
     }
     let text = response.text().await?;
-    parse_single(&text, isbn)
+    let mut data = parse_single(&text, isbn)?;
+    if data.cover.is_empty() {
+        data.cover = cover(client, isbn).await;
+    }
+    Ok(data)
+}
+
+/// URL of the cover from the DNB, if it has one for this isbn.
+async fn cover(client: &Client, isbn: &str) -> String {
+    let url = format!("{COVER_URL}{isbn}");
+    match client.head(&url).send().await {
+        Ok(response)
+            if response.status().is_success()
+                && response
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .is_some_and(|t| t.as_bytes().starts_with(b"image/")) =>
+        {
+            url
+        }
+        _ => String::new(),
+    }
+}
+
+/// Parameters for looking up books without isbn
+#[metadata]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Lookup {
+    pub title: String,
+    pub author: String,
+    /// Year of publication, 0 for any
+    pub year: i64,
+}
+
+impl Lookup {
+    /// Builds the CQL query, all words have to match.
+    fn cql(&self) -> Option<String> {
+        fn terms<'a>(index: &'a str, text: &'a str) -> impl Iterator<Item = String> + 'a {
+            text.split(|c: char| !c.is_alphanumeric())
+                .filter(|w| !w.is_empty())
+                .map(move |w| format!("{index}={w}"))
+        }
+
+        let mut terms: Vec<String> = terms("tit", &self.title)
+            .chain(terms("per", &self.author))
+            .collect();
+        if terms.is_empty() {
+            return None;
+        }
+        if self.year > 0 {
+            terms.push(format!("jhr={}", self.year));
+        }
+        Some(terms.join(" and "))
+    }
+
+    /// Sorts the records by their similarity to the parameters.
+    ///
+    /// Empty parameters are ignored.
+    fn rank(&self, records: &mut [Record]) {
+        // empty patterns cannot be scored
+        let fuzzy = |text: &str| (!text.trim().is_empty()).then(|| Fuzzy::new(text.trim()));
+        let mut title = fuzzy(&self.title);
+        let mut author = fuzzy(&self.author);
+        records.sort_by_cached_key(|r| {
+            let title = title.as_mut().map_or(0, |f| f.score(&r.data.title));
+            let author = author.as_mut().map_or(0, |f| {
+                let authors = r.data.authors.iter().map(|a| f.score(&a.name()));
+                authors.max().unwrap_or_default()
+            });
+            Reverse(title * 3 + author * 2)
+        });
+    }
+}
+
+/// Search the "Deutsche Nationalbibliothek" by title, author and year.
+///
+/// Returns the best matches first.
+pub async fn lookup(client: &Client, params: &Lookup) -> Result<Vec<Record>> {
+    let cql = params.cql().ok_or(Error::Arguments)?;
+    let mut records = query(client, &cql, 0).await?;
+    params.rank(&mut records);
+    Ok(records)
 }
 
-#[allow(unused)]
 pub async fn query(client: &Client, query: &str, page: usize) -> Result<Vec<Record>> {
     let url = Url::parse_with_params(
         URL,
         [
             ("maximumRecords", "100"),
-            ("startRecord", format!("{}", page * 100).as_str()),
+            // starts at 1
+            ("startRecord", format!("{}", page * 100 + 1).as_str()),
             ("query", query),
         ],
     )
@@ -63,157 +143,72 @@ pub async fn query(client: &Client, query: &str, page: usize) -> Result<Vec<Reco
     Ok(results)
 }
 
-const ISBN_COSTS_TAG: &str = "020";
-const ISBN_CODE: &str = "a";
-const COSTS_CODE: &str = "c";
-const EAN_TAG: &str = "024";
-const EAN_CODE: &str = "a";
-const TITLE_TAG: &str = "245";
-const TITLE_CODE: &str = "a";
-const SUBTITLE_CODE: &str = "p";
-const AUTHOR_TAG: &str = "100";
-const AUTHOR_CODE: &str = "a";
-const PERSON_TAG: &str = "700";
-const PERSON_CODE: &str = "a";
-const PUBLISHER_TAG: &str = "264";
-const PUBLISHER_CODE: &str = "b";
-
-/// Warning: legacy: 1 DM => 0.51129 EUR
-const DM_TO_EUR: f64 = 0.51129;
-
-/// If the title is shorter than this, the subtitle is appended
-const SHORT_TITLE_LEN: usize = 16;
-
-/// MARC21 Parsing
-///
-/// ## See Also
-/// https://www.dnb.de/EN/Professionell/Metadatendienste/Datenbezug/SRU/sru_node.html
-fn parse_single(response: &str, isbn: &str) -> Result<BookData> {
-    let document = roxmltree::Document::parse(response)?;
+#[cfg(test)]
+mod tests {
+    use std::fs;
 
-    let mut first_result = None;
+    use super::*;
+    use crate::db::{Author, AuthorRole};
 
-    if let Some(records) = document
-        .descendants()
-        .find(|n| n.tag_name().name() == "records")
-    {
-        for record in records.children().map(Record::parse) {
-            if record.isbns.iter().any(|e| e == isbn) {
-                return Ok(record.data);
-            }
-            if first_result.is_none() {
-                first_result = Some(record.data);
+    #[test]
+    fn lookup_query() {
+        let lookup = Lookup {
+            title: "Der \"Hobbit\"".into(),
+            author: "J.R.R. Tolkien".into(),
+            year: 2012,
+        };
+        assert_eq!(
+            lookup.cql().unwrap(),
+            "tit=Der and tit=Hobbit and per=J and per=R and per=R and per=Tolkien and jhr=2012"
+        );
+        assert!(
+            Lookup {
+                year: 2012,
+                ..Lookup::default()
             }
-        }
+            .cql()
+            .is_none()
+        );
     }
 
-    first_result.ok_or(Error::NothingFound)
-}
-
-#[derive(Debug, Default)]
-pub struct Record {
-    pub isbns: Vec<String>,
-    pub data: BookData,
-}
-
-impl Record {
-    fn parse(record: roxmltree::Node) -> Self {
-        let mut data = BookData::default();
-        let mut persons = Vec::new();
-        let mut isbns = Vec::new();
-
-        let Some(record) = record.children().find(|n| n.has_tag_name("recordData")) else {
-            return Self::default();
-        };
-        let Some(record) = record.children().find(|n| n.has_tag_name("record")) else {
-            return Self::default();
+    #[test]
+    fn lookup_rank() {
+        let record = |title: &str, author: &str| Record {
+            isbns: vec![],
+            data: BookData {
+                title: title.into(),
+                authors: vec![author.into()],
+                ..BookData::default()
+            },
         };
-
-        for df in record.children().filter(|x| x.has_tag_name("datafield")) {
-            let Some(tag) = df.attribute("tag") else {
-                continue;
-            };
-
-            match tag {
-                ISBN_COSTS_TAG => {
-                    if let Some(t) = subfield(df, ISBN_CODE) {
-                        isbns.push(t)
-                    }
-                    if let Some(t) = subfield(df, COSTS_CODE) {
-                        data.costs = parse_costs(&t);
-                    }
-                }
-                EAN_TAG => subfield(df, EAN_CODE).map_or((), |t| isbns.push(t)),
-                TITLE_TAG => {
-                    if let Some(t) = subfield(df, TITLE_CODE) {
-                        data.title = t;
-                    }
-                    // Add subtitle if the title is to short
-                    if data.title.len() < SHORT_TITLE_LEN
-                        && let Some(t) = subfield(df, SUBTITLE_CODE)
-                        && !t.is_empty()
-                    {
-                        data.title.push_str(" - ");
-                        data.title.push_str(&t);
-                    }
-                }
-                AUTHOR_TAG => {
-                    if let Some(t) = subfield(df, AUTHOR_CODE) {
-                        data.authors.push(t);
-                    }
-                }
-                PERSON_TAG => {
-                    if let Some(t) = subfield(df, PERSON_CODE) {
-                        persons.push(t);
-                    }
-                }
-                PUBLISHER_TAG => {
-                    if let Some(t) = subfield(df, PUBLISHER_CODE) {
-                        data.publisher = t;
-                    }
-                }
-                _ => {}
-            };
+        let mut records = [
+            record("Die Gefährten", "J. R. R. Tolkien"),
+            record("Hobbit-Presse", "Klett-Cotta"),
+            record("Der Hobbit", "J. R. R. Tolkien"),
+        ];
+        Lookup {
+            title: "hobbit".into(),
+            author: "tolkien".into(),
+            year: 0,
         }
-        if data.authors.is_empty() {
-            data.authors = persons;
+        .rank(&mut records);
+        assert_eq!(records[0].data.title, "Der Hobbit");
+        assert_eq!(records[2].data.title, "Die Gefährten");
+
+        // only the title is known
+        Lookup {
+            title: "gefährten".into(),
+            ..Lookup::default()
         }
-        // Reformat author names ("<surname>, <forename>" -> "<forename> <surname>")
-        for author in &mut data.authors {
-            if let Some((s, f)) = author.split_once(',') {
-                *author = [f.trim(), s.trim()].join(" ");
-            }
+        .rank(&mut records);
+        assert_eq!(records[0].data.title, "Die Gefährten");
+        Lookup {
+            author: "klett".into(),
+            ..Lookup::default()
         }
-        Self { isbns, data }
+        .rank(&mut records);
+        assert_eq!(records[0].data.title, "Hobbit-Presse");
     }
-}
-
-fn subfield(datafield: roxmltree::Node, code: &str) -> Option<String> {
-    let subfield = datafield
-        .children()
-        .find(|n| n.has_tag_name("subfield") && n.attribute("code") == Some(code))?;
-
-    Some(subfield.text()?.nfc().filter(|c| !c.is_control()).collect())
-}
-
-fn parse_costs(costs: &str) -> f64 {
-    if let Some((_, suffix)) = costs.split_once("EUR ") {
-        let num = suffix.split_once(' ').map_or(suffix, |s| s.0);
-        num.trim().parse().unwrap_or_default()
-    } else if let Some((_, suffix)) = costs.split_once("DM ") {
-        let num = suffix.split_once(' ').map_or(suffix, |s| s.0);
-        let num: f64 = num.trim().parse().unwrap_or_default();
-        (num * DM_TO_EUR * 100.0).round() / 100.0
-    } else {
-        0.0
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::fs;
-
-    use super::*;
 
     #[test]
     fn parse_single_record() {
@@ -226,6 +221,12 @@ mod tests {
                 authors: vec!["Christopher Paolini".into()],
                 publisher: "cbj".into(),
                 costs: 9.95,
+                year: 2006,
+                edition: "1. Aufl.".into(),
+                language: "ger".into(),
+                pages: 733,
+                series: "C.-Bertelsmann-Taschenbuch ; 30333".into(),
+                ..BookData::default()
             }
         )
     }
@@ -241,6 +242,11 @@ mod tests {
                 authors: vec!["Kin Platt".into()],
                 publisher: "Franckh".into(),
                 costs: 5.01,
+                year: 1978,
+                edition: "4. Aufl., 46. - 60. Tsd.".into(),
+                language: "ger".into(),
+                pages: 152,
+                ..BookData::default()
             }
         )
     }
@@ -254,7 +260,10 @@ mod tests {
             BookData {
                 title: "Das große Tafelwerk interaktiv 2.0".into(),
                 authors: vec![
-                    "Tilman Pehle".into(), // Herrausgeber
+                    Author {
+                        role: AuthorRole::Editor,
+                        ..Author::from("Tilman Pehle")
+                    },
                     "Andreas Gramm".into(),
                     "Hubert König".into(),
                     "Wolfgang Kricke".into(),
@@ -266,37 +275,12 @@ mod tests {
                 ],
                 publisher: "Cornelsen".into(),
                 costs: 12.5,
+                year: 2012,
+                edition: "Formelsammlung für Niedersachsen, 1. Aufl., 1. Dr.".into(),
+                language: "ger".into(),
+                pages: 164,
+                ..BookData::default()
             }
         )
     }
-
-    #[test]
-    fn parse_costs() {
-        fn approx_eq(a: f64, b: f64) -> bool {
-            (a - b).abs() < 8.0 * f64::EPSILON
-        }
-
-        macro_rules! assert_approx_eq {
-            ($left:expr, $right:expr $(,)?) => {
-                assert!(
-                    approx_eq($left, $right),
-                    "assertion failed: `left == right`\n  left: `{:?}`,\n right: `{:?}`",
-                    $left,
-                    $right
-                )
-            };
-        }
-
-        assert_approx_eq!(super::parse_costs("kart. : EUR"), 0.0);
-        assert_approx_eq!(super::parse_costs("kart. : EUR 9.95"), 9.95);
-        assert_approx_eq!(super::parse_costs("Pp. (nicht im Buchhandel)"), 0.0);
-        assert_approx_eq!(
-            super::parse_costs("Pp. : EUR 14.95 (DE), EUR 15.40 (AT), sfr 21.90 (freier Pr.)"),
-            14.95
-        );
-        assert_approx_eq!(
-            super::parse_costs("Lw. : DM 9.80"),
-            (9.80 * DM_TO_EUR * 100.0).round() / 100.0
-        );
-    }
 }
@@ -0,0 +1,241 @@
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use tracing::info;
+
+use super::BookData;
+use crate::db::Author;
+use crate::error::{Error, Result};
+
+const URL: &str = "https://www.googleapis.com/books/v1/volumes";
+
+/// ISO 639-1 codes of Google Books with the corresponding MARC language codes
+const LANGUAGES: &[(&str, &str)] = &[
+    ("ar", "ara"),
+    ("bg", "bul"),
+    ("cs", "cze"),
+    ("da", "dan"),
+    ("de", "ger"),
+    ("el", "gre"),
+    ("en", "eng"),
+    ("es", "spa"),
+    ("et", "est"),
+    ("fa", "per"),
+    ("fi", "fin"),
+    ("fr", "fre"),
+    ("he", "heb"),
+    ("hi", "hin"),
+    ("hr", "hrv"),
+    ("hu", "hun"),
+    ("is", "ice"),
+    ("it", "ita"),
+    ("ja", "jpn"),
+    ("ko", "kor"),
+    ("la", "lat"),
+    ("lt", "lit"),
+    ("lv", "lav"),
+    ("nl", "dut"),
+    ("no", "nor"),
+    ("pl", "pol"),
+    ("pt", "por"),
+    ("ro", "rum"),
+    ("ru", "rus"),
+    ("sk", "slo"),
+    ("sl", "slv"),
+    ("sr", "srp"),
+    ("sv", "swe"),
+    ("tr", "tur"),
+    ("uk", "ukr"),
+    ("zh", "chi"),
+];
+
+#[derive(Deserialize)]
+struct Volumes {
+    #[serde(default)]
+    items: Vec<Volume>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Volume {
+    volume_info: VolumeInfo,
+    #[serde(default)]
+    sale_info: Option<SaleInfo>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VolumeInfo {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    publisher: String,
+    #[serde(default)]
+    industry_identifiers: Vec<Identifier>,
+    #[serde(default)]
+    published_date: String,
+    #[serde(default)]
+    page_count: u32,
+    #[serde(default)]
+    categories: Vec<String>,
+    #[serde(default)]
+    language: String,
+    #[serde(default)]
+    image_links: Option<ImageLinks>,
+}
+
+#[derive(Deserialize)]
+struct Identifier {
+    identifier: String,
+}
+
+#[derive(Deserialize)]
+struct ImageLinks {
+    thumbnail: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SaleInfo {
+    list_price: Option<Price>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Price {
+    amount: f64,
+    currency_code: String,
+}
+
+/// Try fetching the book data from Google Books
+///
+/// ## See Also
+/// https://developers.google.com/books/docs/v1/using
+pub async fn fetch(client: &Client, isbn: &str) -> Result<BookData> {
+    let url = Url::parse_with_params(URL, [("q", &format!("isbn:{isbn}"))])
+        .map_err(|_| Error::Arguments)?;
+    info!("Fetch {url}");
+    let response = client.get(url).send().await?;
+    if !response.status().is_success() {
+        return Err(Error::Network);
+    }
+    let text = response.text().await?;
+    parse(&text, isbn)
+}
+
+fn parse(response: &str, isbn: &str) -> Result<BookData> {
+    let Volumes { mut items } = serde_json::from_str(response)?;
+
+    // Prefer the volume with the exact isbn
+    let index = items
+        .iter()
+        .position(|v| {
+            v.volume_info
+                .industry_identifiers
+                .iter()
+                .any(|i| i.identifier == isbn)
+        })
+        .unwrap_or_default();
+    if index >= items.len() {
+        return Err(Error::NothingFound);
+    }
+    let Volume {
+        volume_info,
+        sale_info,
+    } = items.swap_remove(index);
+
+    let costs = sale_info
+        .and_then(|s| s.list_price)
+        .filter(|p| p.currency_code == "EUR")
+        .map_or(0.0, |p| p.amount);
+
+    Ok(BookData {
+        title: volume_info.title.trim().into(),
+        authors: volume_info
+            .authors
+            .into_iter()
+            .map(|a| Author::parse(&a))
+            .collect(),
+        publisher: volume_info.publisher.trim().into(),
+        costs,
+        // "YYYY-MM-DD", "YYYY-MM" or "YYYY"
+        year: volume_info
+            .published_date
+            .get(..4)
+            .and_then(|y| y.parse().ok())
+            .unwrap_or_default(),
+        edition: String::new(),
+        language: marc_language(&volume_info.language),
+        pages: volume_info.page_count,
+        series: String::new(),
+        subjects: volume_info.categories,
+        ddc: String::new(),
+        cover: volume_info
+            .image_links
+            .map(|i| i.thumbnail.replacen("http://", "https://", 1))
+            .unwrap_or_default(),
+    })
+}
+
+/// Converts the language code, e.g., "en" or "pt-BR", to the MARC language code.
+///
+/// Unknown languages are left empty, so that they can be filled by other providers.
+fn marc_language(code: &str) -> String {
+    let code = code
+        .split('-')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|(iso, _)| *iso == code)
+        .map(|(_, marc)| marc.to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn parse_volume() {
+        let response =
+            fs::read_to_string("test/google/google-response_9780261102217.json").unwrap();
+        let data = parse(&response, "9780261102217").unwrap();
+        assert_eq!(
+            data,
+            BookData {
+                title: "The Hobbit".into(),
+                authors: vec!["J. R. R. Tolkien".into()],
+                publisher: "HarperCollins".into(),
+                costs: 9.49,
+                year: 1995,
+                language: "eng".into(),
+                pages: 310,
+                subjects: vec!["Fiction".into()],
+                cover: "https://books.google.com/books/content?id=U799AY3yfqcC&printsec=frontcover&img=1&zoom=1".into(),
+                ..BookData::default()
+            }
+        );
+    }
+
+    #[test]
+    fn language_codes() {
+        assert_eq!(marc_language("de"), "ger");
+        assert_eq!(marc_language("pt-BR"), "por");
+        assert_eq!(marc_language("un"), "");
+        assert_eq!(marc_language(""), "");
+    }
+
+    #[test]
+    fn parse_empty() {
+        let response = fs::read_to_string("test/google/google-response_empty.json").unwrap();
+        assert!(matches!(
+            parse(&response, "9780261102217"),
+            Err(Error::NothingFound)
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+use reqwest::{Client, Url};
+use tracing::info;
+
+use super::BookData;
+use super::marc::parse_single;
+use crate::error::{Error, Result};
+
+const URL: &str =
+    "https://sru.k10plus.de/opac-de-627?version=1.1&operation=searchRetrieve&recordSchema=marcxml";
+
+/// Try fetching the book data from the K10plus union catalogue
+///
+/// ## See Also
+/// https://wiki.k10plus.de/display/K10PLUS/SRU
+pub async fn fetch(client: &Client, isbn: &str) -> Result<BookData> {
+    let url = Url::parse_with_params(
+        URL,
+        [
+            ("maximumRecords", "10"),
+            ("query", &format!("pica.isb={isbn}")),
+        ],
+    )
+    .map_err(|_| Error::Arguments)?;
+    info!("Fetch {url}");
+    let response = client.get(url).send().await?;
+    if !response.status().is_success() {
+        return Err(Error::Network);
+    }
+    let text = response.text().await?;
+    parse_single(&text, isbn)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn parse_record() {
+        let response =
+            fs::read_to_string("test/k10plus/k10plus-response_9780261102217.xml").unwrap();
+        let data = parse_single(&response, "9780261102217").unwrap();
+        assert_eq!(
+            data,
+            BookData {
+                title: "The hobbit".into(),
+                authors: vec!["J. R. R. Tolkien".into()],
+                publisher: "HarperCollins".into(),
+                costs: 0.0,
+                year: 1995,
+                edition: "Reset ed.".into(),
+                language: "eng".into(),
+                pages: 310,
+                ..BookData::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_empty() {
+        let response = fs::read_to_string("test/k10plus/k10plus-response_empty.xml").unwrap();
+        assert!(matches!(
+            parse_single(&response, "9780261102217"),
+            Err(Error::NothingFound)
+        ));
+    }
+}
@@ -0,0 +1,850 @@
+use std::io;
+
+use gluer::metadata;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+use super::BookData;
+use crate::db::{Author, AuthorRole, Book, Condition};
+use crate::error::{Error, Result};
+use crate::isbn;
+
+const CONTROL_NUMBER_TAG: &str = "001";
+const FIXED_DATA_TAG: &str = "008";
+const ISBN_COSTS_TAG: &str = "020";
+const ISBN_CODE: &str = "a";
+const COSTS_CODE: &str = "c";
+const EAN_TAG: &str = "024";
+const EAN_CODE: &str = "a";
+const LANGUAGE_TAG: &str = "041";
+const LANGUAGE_CODE: &str = "a";
+const DDC_TAG: &str = "082";
+const DDC_CODE: &str = "a";
+const DDC_SOURCE_CODE: &str = "2";
+/// Source of the DNB subject groups, which are stored in the DDC field
+const DNB_SUBJECT_GROUPS: &str = "sdnb";
+const TITLE_TAG: &str = "245";
+const TITLE_CODE: &str = "a";
+const SUBTITLE_CODE: &str = "p";
+const AUTHOR_TAG: &str = "100";
+const AUTHOR_CODE: &str = "a";
+const PERSON_TAG: &str = "700";
+const PERSON_CODE: &str = "a";
+const RELATOR_CODE: &str = "4";
+const PUBLISHER_TAG: &str = "264";
+const PUBLISHER_CODE: &str = "b";
+const PUBLISHER_DATE_CODE: &str = "c";
+const EDITION_TAG: &str = "250";
+const EDITION_CODE: &str = "a";
+const EXTENT_TAG: &str = "300";
+const EXTENT_CODE: &str = "a";
+const SERIES_TAG: &str = "490";
+const SERIES_CODE: &str = "a";
+const SERIES_VOLUME_CODE: &str = "v";
+const NOTE_TAG: &str = "500";
+const NOTE_CODE: &str = "a";
+const SUBJECT_TAG: &str = "650";
+const SUBJECT_CODE: &str = "a";
+const LOCATION_TAG: &str = "852";
+const LOCATION_CATEGORY_CODE: &str = "c";
+const LOCATION_ID_CODE: &str = "j";
+const LINK_TAG: &str = "856";
+const LINK_URL_CODE: &str = "u";
+const LINK_MATERIAL_CODE: &str = "3";
+const COVER_MATERIAL: &str = "Cover";
+
+/// Positions of the publication year and language in the fixed-length data
+const FIXED_DATA_YEAR: std::ops::Range<usize> = 7..11;
+const FIXED_DATA_LANGUAGE: std::ops::Range<usize> = 35..38;
+
+/// Leader of exported records: new, language material, monograph, UTF-8
+const LEADER: &str = "00000nam a2200000   4500";
+
+/// Warning: legacy: 1 DM => 0.51129 EUR
+const DM_TO_EUR: f64 = 0.51129;
+
+/// If the title is shorter than this, the subtitle is appended
+const SHORT_TITLE_LEN: usize = 16;
+
+/// ISO 2709 separators
+const RECORD_TERMINATOR: u8 = 0x1d;
+const FIELD_TERMINATOR: u8 = 0x1e;
+const SUBFIELD_DELIMITER: u8 = 0x1f;
+
+/// Encoding of MARC records
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MarcFormat {
+    /// MARC21-xml collection
+    #[default]
+    Xml,
+    /// Binary exchange format (ISO 2709)
+    Iso2709,
+}
+
+impl MarcFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            MarcFormat::Xml => "application/marcxml+xml",
+            MarcFormat::Iso2709 => "application/marc",
+        }
+    }
+    pub fn extension(self) -> &'static str {
+        match self {
+            MarcFormat::Xml => "xml",
+            MarcFormat::Iso2709 => "mrc",
+        }
+    }
+}
+
+/// A catalogue record with its isbns
+#[metadata]
+#[derive(Debug, Default, Serialize)]
+pub struct Record {
+    pub isbns: Vec<String>,
+    pub data: BookData,
+}
+
+impl Record {
+    /// Parses a MARC21-xml record, which might be wrapped into an SRU record.
+    pub fn parse(record: roxmltree::Node) -> Self {
+        let mut data = BookData::default();
+        let mut persons = Vec::new();
+        let mut isbns = Vec::new();
+
+        // Unwrap SRU records
+        let record =
+            if let Some(record_data) = record.children().find(|n| n.has_tag_name("recordData")) {
+                let Some(record) = record_data.children().find(|n| n.has_tag_name("record")) else {
+                    return Self::default();
+                };
+                record
+            } else {
+                record
+            };
+
+        // Fallback for the year and language
+        let fixed_data = record
+            .children()
+            .find(|n| n.has_tag_name("controlfield") && n.attribute("tag") == Some(FIXED_DATA_TAG))
+            .and_then(|n| n.text())
+            .unwrap_or_default();
+
+        for df in record.children().filter(|x| x.has_tag_name("datafield")) {
+            let Some(tag) = df.attribute("tag") else {
+                continue;
+            };
+
+            match tag {
+                ISBN_COSTS_TAG => {
+                    if let Some(t) = subfield(df, ISBN_CODE) {
+                        isbns.push(t)
+                    }
+                    if let Some(t) = subfield(df, COSTS_CODE) {
+                        data.costs = parse_costs(&t);
+                    }
+                }
+                EAN_TAG => subfield(df, EAN_CODE).map_or((), |t| isbns.push(t)),
+                TITLE_TAG => {
+                    if let Some(t) = subfield(df, TITLE_CODE) {
+                        data.title = t;
+                    }
+                    // Add subtitle if the title is to short
+                    if data.title.len() < SHORT_TITLE_LEN
+                        && let Some(t) = subfield(df, SUBTITLE_CODE)
+                        && !t.is_empty()
+                    {
+                        data.title.push_str(" - ");
+                        data.title.push_str(&t);
+                    }
+                }
+                AUTHOR_TAG => {
+                    if let Some(t) = person(df, AUTHOR_CODE) {
+                        data.authors.push(t);
+                    }
+                }
+                PERSON_TAG => {
+                    if let Some(t) = person(df, PERSON_CODE) {
+                        persons.push(t);
+                    }
+                }
+                PUBLISHER_TAG => {
+                    if let Some(t) = subfield(df, PUBLISHER_CODE) {
+                        data.publisher = t;
+                    }
+                    if let Some(t) = subfield(df, PUBLISHER_DATE_CODE) {
+                        data.year = parse_year(&t);
+                    }
+                }
+                LANGUAGE_TAG => {
+                    if let Some(t) = subfield(df, LANGUAGE_CODE) {
+                        data.language = t;
+                    }
+                }
+                DDC_TAG => {
+                    let source = subfield(df, DDC_SOURCE_CODE).unwrap_or_default();
+                    if !source.contains(DNB_SUBJECT_GROUPS)
+                        && let Some(t) = subfield(df, DDC_CODE)
+                    {
+                        data.ddc = t;
+                    }
+                }
+                EDITION_TAG => {
+                    if let Some(t) = subfield(df, EDITION_CODE) {
+                        data.edition = t;
+                    }
+                }
+                EXTENT_TAG => {
+                    if let Some(t) = subfield(df, EXTENT_CODE) {
+                        data.pages = parse_pages(&t);
+                    }
+                }
+                SERIES_TAG => {
+                    if let Some(t) = subfield(df, SERIES_CODE) {
+                        data.series = t;
+                    }
+                    if let Some(t) = subfield(df, SERIES_VOLUME_CODE) {
+                        data.series.push_str(" ; ");
+                        data.series.push_str(&t);
+                    }
+                }
+                SUBJECT_TAG => {
+                    if let Some(t) = subfield(df, SUBJECT_CODE)
+                        && !data.subjects.contains(&t)
+                    {
+                        data.subjects.push(t);
+                    }
+                }
+                LINK_TAG => {
+                    if subfield(df, LINK_MATERIAL_CODE).as_deref() == Some(COVER_MATERIAL)
+                        && let Some(t) = subfield(df, LINK_URL_CODE)
+                    {
+                        data.cover = t;
+                    }
+                }
+                _ => {}
+            };
+        }
+        if data.authors.is_empty() {
+            data.authors = persons;
+        }
+        if data.year == 0 {
+            data.year = fixed_data.get(FIXED_DATA_YEAR).map_or(0, parse_year);
+        }
+        if data.language.is_empty()
+            && let Some(language) = fixed_data.get(FIXED_DATA_LANGUAGE)
+            && language.bytes().all(|b| b.is_ascii_lowercase())
+        {
+            data.language = language.into();
+        }
+        Self { isbns, data }
+    }
+}
+
+impl Record {
+    /// Converts the record into a new book without id.
+    pub fn into_book(mut self, category: &str) -> Book {
+        Book {
+            id: String::new(),
+            isbn: self
+                .isbns
+                .iter()
+                .find_map(|i| isbn::parse(i).ok())
+                .or_else(|| self.isbns.pop())
+                .unwrap_or_default(),
+            title: self.data.title,
+            publisher: self.data.publisher,
+            year: self.data.year,
+            costs: self.data.costs,
+            note: String::new(),
+            borrowable: true,
+            category: category.into(),
+            authors: self.data.authors,
+            borrower: None,
+            reservation: None,
+            edition: self.data.edition,
+            language: self.data.language,
+            pages: self.data.pages,
+            series: self.data.series,
+            subjects: self.data.subjects,
+            ddc: self.data.ddc,
+            cover: self.data.cover,
+            added: None,
+            condition: Condition::Good,
+        }
+    }
+}
+
+/// Parses an SRU response with MARC21-xml records.
+///
+/// Returns the record with the given isbn (in either form) or the first one.
+///
+/// ## See Also
+/// https://www.dnb.de/EN/Professionell/Metadatendienste/Datenbezug/SRU/sru_node.html
+pub fn parse_single(response: &str, isbn: &str) -> Result<BookData> {
+    let document = roxmltree::Document::parse(response)?;
+
+    let mut first_result = None;
+
+    if let Some(records) = document
+        .descendants()
+        .find(|n| n.tag_name().name() == "records")
+    {
+        for record in records.children().map(Record::parse) {
+            if record
+                .isbns
+                .iter()
+                .any(|e| e == isbn || isbn::same(e, isbn))
+            {
+                return Ok(record.data);
+            }
+            if first_result.is_none() {
+                first_result = Some(record.data);
+            }
+        }
+    }
+
+    first_result.ok_or(Error::NothingFound)
+}
+
+/// Parses all records of a MARC21-xml or ISO 2709 file.
+pub fn parse_file(file: &[u8]) -> Result<Vec<Record>> {
+    let xml = if file.trim_ascii_start().starts_with(b"<") {
+        String::from_utf8(file.to_vec()).map_err(|_| Error::InvalidFormat)?
+    } else {
+        iso2709_to_xml(file)?
+    };
+    let document = roxmltree::Document::parse(&xml)?;
+    Ok(document
+        .descendants()
+        .filter(|n| n.has_tag_name("record") && n.children().any(|c| c.has_tag_name("leader")))
+        .map(Record::parse)
+        .collect())
+}
+
+fn subfield(datafield: roxmltree::Node, code: &str) -> Option<String> {
+    let subfield = datafield
+        .children()
+        .find(|n| n.has_tag_name("subfield") && n.attribute("code") == Some(code))?;
+
+    Some(subfield.text()?.nfc().filter(|c| !c.is_control()).collect())
+}
+
+/// Returns the first four digit number, e.g. "© 1970" or "[2012]".
+pub fn parse_year(date: &str) -> i64 {
+    date.split(|c: char| !c.is_ascii_digit())
+        .find(|n| n.len() == 4)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Returns the first number of the extent, e.g. "XII, 310 S.".
+fn parse_pages(extent: &str) -> u32 {
+    extent
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|n| !n.is_empty())
+        .and_then(|n| n.parse().ok())
+        .unwrap_or_default()
+}
+
+fn parse_costs(costs: &str) -> f64 {
+    if let Some((_, suffix)) = costs.split_once("EUR ") {
+        let num = suffix.split_once(' ').map_or(suffix, |s| s.0);
+        num.trim().parse().unwrap_or_default()
+    } else if let Some((_, suffix)) = costs.split_once("DM ") {
+        let num = suffix.split_once(' ').map_or(suffix, |s| s.0);
+        let num: f64 = num.trim().parse().unwrap_or_default();
+        (num * DM_TO_EUR * 100.0).round() / 100.0
+    } else {
+        0.0
+    }
+}
+
+/// A single MARC field
+enum Field {
+    Control(&'static str, String),
+    Data(&'static str, Vec<(&'static str, String)>),
+}
+
+/// Parses a person with the name as "<surname>, <forename>" and the relator code.
+fn person(datafield: roxmltree::Node, code: &str) -> Option<Author> {
+    let name = subfield(datafield, code)?;
+    Some(Author {
+        role: subfield(datafield, RELATOR_CODE).map_or(AuthorRole::Author, |r| role(&r)),
+        ..Author::parse(&name)
+    })
+}
+
+/// MARC relator code of the role
+///
+/// ## See Also
+/// https://www.loc.gov/marc/relators/relaterm.html
+fn relator(role: AuthorRole) -> &'static str {
+    match role {
+        AuthorRole::Author => "aut",
+        AuthorRole::Editor => "edt",
+        AuthorRole::Illustrator => "ill",
+    }
+}
+
+fn role(relator: &str) -> AuthorRole {
+    match relator {
+        "edt" => AuthorRole::Editor,
+        "ill" => AuthorRole::Illustrator,
+        _ => AuthorRole::Author,
+    }
+}
+
+/// Converts the book into MARC fields, empty fields are omitted.
+fn fields(book: &Book) -> Vec<Field> {
+    let mut fields = vec![Field::Control(CONTROL_NUMBER_TAG, book.id.clone())];
+
+    let mut isbn = Vec::new();
+    if !book.isbn.is_empty() {
+        isbn.push((ISBN_CODE, book.isbn.clone()));
+    }
+    if book.costs > 0.0 {
+        isbn.push((COSTS_CODE, format!("EUR {:.2}", book.costs)));
+    }
+    if !isbn.is_empty() {
+        fields.push(Field::Data(ISBN_COSTS_TAG, isbn));
+    }
+    if !book.language.is_empty() {
+        fields.push(Field::Data(
+            LANGUAGE_TAG,
+            vec![(LANGUAGE_CODE, book.language.clone())],
+        ));
+    }
+    if !book.ddc.is_empty() {
+        fields.push(Field::Data(DDC_TAG, vec![(DDC_CODE, book.ddc.clone())]));
+    }
+
+    let mut authors = book.authors.iter().map(|a| {
+        let name = if a.forename.is_empty() {
+            a.surname.clone()
+        } else {
+            format!("{}, {}", a.surname, a.forename)
+        };
+        (name, relator(a.role).to_string())
+    });
+    if let Some((author, relator)) = authors.next() {
+        fields.push(Field::Data(
+            AUTHOR_TAG,
+            vec![(AUTHOR_CODE, author), (RELATOR_CODE, relator)],
+        ));
+    }
+
+    fields.push(Field::Data(
+        TITLE_TAG,
+        vec![(TITLE_CODE, book.title.clone())],
+    ));
+    if !book.edition.is_empty() {
+        fields.push(Field::Data(
+            EDITION_TAG,
+            vec![(EDITION_CODE, book.edition.clone())],
+        ));
+    }
+
+    let mut publisher = Vec::new();
+    if !book.publisher.is_empty() {
+        publisher.push((PUBLISHER_CODE, book.publisher.clone()));
+    }
+    if book.year > 0 {
+        publisher.push((PUBLISHER_DATE_CODE, book.year.to_string()));
+    }
+    if !publisher.is_empty() {
+        fields.push(Field::Data(PUBLISHER_TAG, publisher));
+    }
+    if book.pages > 0 {
+        fields.push(Field::Data(
+            EXTENT_TAG,
+            vec![(EXTENT_CODE, format!("{} p.", book.pages))],
+        ));
+    }
+    if !book.series.is_empty() {
+        fields.push(Field::Data(
+            SERIES_TAG,
+            vec![(SERIES_CODE, book.series.clone())],
+        ));
+    }
+
+    if !book.note.is_empty() {
+        fields.push(Field::Data(NOTE_TAG, vec![(NOTE_CODE, book.note.clone())]));
+    }
+    for subject in &book.subjects {
+        fields.push(Field::Data(
+            SUBJECT_TAG,
+            vec![(SUBJECT_CODE, subject.clone())],
+        ));
+    }
+    for (person, relator) in authors {
+        fields.push(Field::Data(
+            PERSON_TAG,
+            vec![(PERSON_CODE, person), (RELATOR_CODE, relator)],
+        ));
+    }
+    fields.push(Field::Data(
+        LOCATION_TAG,
+        vec![
+            (LOCATION_CATEGORY_CODE, book.category.clone()),
+            (LOCATION_ID_CODE, book.id.clone()),
+        ],
+    ));
+    if !book.cover.is_empty() {
+        fields.push(Field::Data(
+            LINK_TAG,
+            vec![
+                (LINK_MATERIAL_CODE, COVER_MATERIAL.into()),
+                (LINK_URL_CODE, book.cover.clone()),
+            ],
+        ));
+    }
+    fields
+}
+
+/// Writes the books as MARC records in the given format.
+pub fn write(writer: impl io::Write, format: MarcFormat, books: &[Book]) -> Result<()> {
+    match format {
+        MarcFormat::Xml => write_xml(writer, books),
+        MarcFormat::Iso2709 => write_iso2709(writer, books),
+    }
+}
+
+fn write_xml(mut writer: impl io::Write, books: &[Book]) -> Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<collection xmlns="http://www.loc.gov/MARC21/slim">"#
+    )?;
+    for book in books {
+        writeln!(writer, "<record>")?;
+        writeln!(writer, "  <leader>{LEADER}</leader>")?;
+        for field in fields(book) {
+            match field {
+                Field::Control(tag, value) => writeln!(
+                    writer,
+                    r#"  <controlfield tag="{tag}">{}</controlfield>"#,
+                    escape(&value)
+                )?,
+                Field::Data(tag, subfields) => {
+                    writeln!(writer, r#"  <datafield tag="{tag}" ind1=" " ind2=" ">"#)?;
+                    for (code, value) in subfields {
+                        writeln!(
+                            writer,
+                            r#"    <subfield code="{code}">{}</subfield>"#,
+                            escape(&value)
+                        )?;
+                    }
+                    writeln!(writer, "  </datafield>")?;
+                }
+            }
+        }
+        writeln!(writer, "</record>")?;
+    }
+    writeln!(writer, "</collection>")?;
+    Ok(())
+}
+
+fn write_iso2709(mut writer: impl io::Write, books: &[Book]) -> Result<()> {
+    for book in books {
+        let mut directory = Vec::new();
+        let mut data = Vec::new();
+        for field in fields(book) {
+            let start = data.len();
+            let tag = match field {
+                Field::Control(tag, value) => {
+                    data.extend_from_slice(value.as_bytes());
+                    tag
+                }
+                Field::Data(tag, subfields) => {
+                    data.extend_from_slice(b"  ");
+                    for (code, value) in subfields {
+                        data.push(SUBFIELD_DELIMITER);
+                        data.extend_from_slice(code.as_bytes());
+                        data.extend_from_slice(value.as_bytes());
+                    }
+                    tag
+                }
+            };
+            data.push(FIELD_TERMINATOR);
+            let len = data.len() - start;
+            if len > 9999 {
+                return Err(Error::InvalidBook);
+            }
+            directory.extend_from_slice(format!("{tag}{len:04}{start:05}").as_bytes());
+        }
+        directory.push(FIELD_TERMINATOR);
+        data.push(RECORD_TERMINATOR);
+
+        let base = LEADER.len() + directory.len();
+        let len = base + data.len();
+        if len > 99999 {
+            return Err(Error::InvalidBook);
+        }
+        write!(
+            writer,
+            "{len:05}{}{base:05}{}",
+            &LEADER[5..12],
+            &LEADER[17..]
+        )?;
+        writer.write_all(&directory)?;
+        writer.write_all(&data)?;
+    }
+    Ok(())
+}
+
+/// Converts binary ISO 2709 records into a MARC21-xml collection.
+///
+/// Records that are not encoded in UTF-8, like the MARC-8 or Latin-1 exports
+/// of older systems, are read as Latin-1, as are invalid UTF-8 records.
+fn iso2709_to_xml(file: &[u8]) -> Result<String> {
+    fn number(bytes: &[u8]) -> Result<usize> {
+        std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|n| n.trim().parse().ok())
+            .ok_or(Error::InvalidFormat)
+    }
+    fn text(bytes: &[u8], unicode: bool) -> String {
+        match std::str::from_utf8(bytes) {
+            Ok(text) if unicode => escape(text),
+            _ => escape(&bytes.iter().map(|&b| b as char).collect::<String>()),
+        }
+    }
+
+    let mut xml = String::from(r#"<collection xmlns="http://www.loc.gov/MARC21/slim">"#);
+    for record in file.split(|&b| b == RECORD_TERMINATOR) {
+        let record = record.trim_ascii();
+        if record.is_empty() {
+            continue;
+        }
+        let leader = record.get(..LEADER.len()).ok_or(Error::InvalidFormat)?;
+        let base = number(&leader[12..17])?;
+        // character coding scheme
+        let text = |bytes: &[u8]| text(bytes, leader[9] == b'a');
+        let directory = record
+            .get(LEADER.len()..base.saturating_sub(1))
+            .ok_or(Error::InvalidFormat)?;
+
+        xml.push_str("<record><leader>");
+        xml.push_str(&text(leader));
+        xml.push_str("</leader>");
+        for entry in directory.chunks(12) {
+            let [t0, t1, t2, ..] = *entry else {
+                return Err(Error::InvalidFormat);
+            };
+            let tag = text(&[t0, t1, t2]);
+            let len = number(entry.get(3..7).ok_or(Error::InvalidFormat)?)?;
+            let start = number(entry.get(7..12).ok_or(Error::InvalidFormat)?)?;
+            let field = record
+                .get(base + start..base + start + len)
+                .ok_or(Error::InvalidFormat)?;
+            let field = field.strip_suffix(&[FIELD_TERMINATOR]).unwrap_or(field);
+
+            if tag.starts_with("00") {
+                xml.push_str(&format!(
+                    r#"<controlfield tag="{tag}">{}</controlfield>"#,
+                    text(field)
+                ));
+            } else {
+                let mut subfields = field.split(|&b| b == SUBFIELD_DELIMITER);
+                let indicators = subfields.next().unwrap_or_default();
+                let ind = |i: usize| indicators.get(i).map_or(' ', |&c| c as char);
+                xml.push_str(&format!(
+                    r#"<datafield tag="{tag}" ind1="{}" ind2="{}">"#,
+                    ind(0),
+                    ind(1)
+                ));
+                for subfield in subfields {
+                    if let Some((&code, value)) = subfield.split_first() {
+                        xml.push_str(&format!(
+                            r#"<subfield code="{}">{}</subfield>"#,
+                            escape(&(code as char).to_string()),
+                            text(value)
+                        ));
+                    }
+                }
+                xml.push_str("</datafield>");
+            }
+        }
+        xml.push_str("</record>");
+    }
+    xml.push_str("</collection>");
+    Ok(xml)
+}
+
+/// Escapes the special XML characters and removes control characters.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn book() -> Book {
+        Book {
+            id: "FANT PAOL 1".into(),
+            isbn: "9783570303337".into(),
+            title: "Eragon - Das Vermächtnis der <Drachenreiter>".into(),
+            publisher: "cbj".into(),
+            year: 2006,
+            costs: 9.95,
+            note: "Signed & sealed".into(),
+            borrowable: true,
+            category: "FANT".into(),
+            authors: vec!["Christopher Paolini".into(), "Joe Doe".into()],
+            edition: "1. Aufl.".into(),
+            language: "ger".into(),
+            pages: 733,
+            series: "C.-Bertelsmann-Taschenbuch ; 30333".into(),
+            subjects: vec!["Fantasy".into(), "Drachen".into()],
+            ddc: "813.6".into(),
+            cover: "https://example.com/cover.jpg".into(),
+            ..Book::default()
+        }
+    }
+
+    #[test]
+    fn xml_roundtrip() {
+        let mut file = Vec::new();
+        write(&mut file, MarcFormat::Xml, &[book()]).unwrap();
+        let records = parse_file(&file).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].isbns, ["9783570303337"]);
+        assert_eq!(
+            records[0].data,
+            BookData {
+                title: "Eragon - Das Vermächtnis der <Drachenreiter>".into(),
+                authors: vec!["Christopher Paolini".into()],
+                publisher: "cbj".into(),
+                costs: 9.95,
+                year: 2006,
+                edition: "1. Aufl.".into(),
+                language: "ger".into(),
+                pages: 733,
+                series: "C.-Bertelsmann-Taschenbuch ; 30333".into(),
+                subjects: vec!["Fantasy".into(), "Drachen".into()],
+                ddc: "813.6".into(),
+                cover: "https://example.com/cover.jpg".into(),
+            }
+        );
+        let book = records.into_iter().next().unwrap().into_book("FANT");
+        assert_eq!(book.year, 2006);
+        assert_eq!(book.subjects, ["Fantasy", "Drachen"]);
+    }
+
+    #[test]
+    fn iso2709_roundtrip() {
+        let mut file = Vec::new();
+        write(&mut file, MarcFormat::Iso2709, &[book(), book()]).unwrap();
+        assert_eq!(file.iter().filter(|&&b| b == RECORD_TERMINATOR).count(), 2);
+        let len: usize = std::str::from_utf8(&file[..5]).unwrap().parse().unwrap();
+        assert_eq!(file[len - 1], RECORD_TERMINATOR);
+
+        let records = parse_file(&file).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].isbns, ["9783570303337"]);
+        assert_eq!(records[1].data.title, book().title);
+        assert_eq!(
+            records[1].data.authors,
+            [Author::from("Christopher Paolini")]
+        );
+        assert_eq!(records[1].data.costs, 9.95);
+    }
+
+    #[test]
+    fn iso2709_latin1() {
+        let mut file = Vec::new();
+        let book = Book {
+            publisher: "_berreuter".into(),
+            ..book()
+        };
+        write(&mut file, MarcFormat::Iso2709, &[book]).unwrap();
+        // "Ü" in Latin-1, with MARC-8 as coding scheme
+        let pos = file.iter().position(|&b| b == b'_').unwrap();
+        file[pos] = 0xdc;
+        file[9] = b' ';
+
+        let records = parse_file(&file).unwrap();
+        assert_eq!(records[0].data.publisher, "Überreuter");
+    }
+
+    #[test]
+    fn parse_sru_response() {
+        let response = fs::read_to_string("test/dnb/dnb-response_3440040585.xml").unwrap();
+        let records = parse_file(response.as_bytes()).unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(
+            records
+                .iter()
+                .any(|r| r.isbns.iter().any(|i| i == "3440040585"))
+        );
+    }
+
+    #[test]
+    fn parse_costs() {
+        fn approx_eq(a: f64, b: f64) -> bool {
+            (a - b).abs() < 8.0 * f64::EPSILON
+        }
+
+        macro_rules! assert_approx_eq {
+            ($left:expr, $right:expr $(,)?) => {
+                assert!(
+                    approx_eq($left, $right),
+                    "assertion failed: `left == right`\n  left: `{:?}`,\n right: `{:?}`",
+                    $left,
+                    $right
+                )
+            };
+        }
+
+        assert_approx_eq!(super::parse_costs("kart. : EUR"), 0.0);
+        assert_approx_eq!(super::parse_costs("kart. : EUR 9.95"), 9.95);
+        assert_approx_eq!(super::parse_costs("Pp. (nicht im Buchhandel)"), 0.0);
+        assert_approx_eq!(
+            super::parse_costs("Pp. : EUR 14.95 (DE), EUR 15.40 (AT), sfr 21.90 (freier Pr.)"),
+            14.95
+        );
+        assert_approx_eq!(
+            super::parse_costs("Lw. : DM 9.80"),
+            (9.80 * DM_TO_EUR * 100.0).round() / 100.0
+        );
+    }
+
+    #[test]
+    fn parse_fixed_data() {
+        let xml = r#"<collection xmlns="http://www.loc.gov/MARC21/slim"><record>
+            <leader>00000nam a2200000   4500</leader>
+            <controlfield tag="008">950627s1995    xxk|||||      00| ||eng c</controlfield>
+            <datafield tag="245" ind1=" " ind2=" "><subfield code="a">The hobbit</subfield></datafield>
+            <datafield tag="300" ind1=" " ind2=" "><subfield code="a">XII, 310 S.</subfield></datafield>
+        </record></collection>"#;
+        let records = parse_file(xml.as_bytes()).unwrap();
+        assert_eq!(records[0].data.year, 1995);
+        assert_eq!(records[0].data.language, "eng");
+        assert_eq!(records[0].data.pages, 310);
+
+        assert_eq!(parse_year("© 1970"), 1970);
+        assert_eq!(parse_year("[2012]"), 2012);
+        assert_eq!(parse_year("s.a."), 0);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse_file(b"00042nam a2200000   4500").is_err());
+        assert!(parse_file(b"<collection>").is_err());
+    }
+}
@@ -1,2 +1,189 @@
+use gluer::metadata;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::db::Author;
+use crate::error::{Error, Result};
+
+pub mod cache;
 pub mod dnb;
+pub mod google;
+pub mod k10plus;
+pub mod marc;
+pub mod openlibrary;
 pub mod user;
+
+/// Book metadata from an external provider
+#[metadata]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BookData {
+    pub title: String,
+    pub authors: Vec<Author>,
+    pub publisher: String,
+    pub costs: f64,
+    /// Year of publication, 0 if unknown
+    pub year: i64,
+    pub edition: String,
+    /// MARC language code (e.g. "ger" or "eng")
+    pub language: String,
+    /// Number of pages, 0 if unknown
+    pub pages: u32,
+    pub series: String,
+    pub subjects: Vec<String>,
+    /// Dewey Decimal Classification
+    pub ddc: String,
+    /// URL of a cover image
+    pub cover: String,
+}
+
+impl BookData {
+    /// Fill the missing fields with the ones from `other`.
+    fn merge(&mut self, other: BookData) {
+        if self.title.is_empty() {
+            self.title = other.title;
+        }
+        if self.authors.is_empty() {
+            self.authors = other.authors;
+        }
+        if self.publisher.is_empty() {
+            self.publisher = other.publisher;
+        }
+        if self.costs == 0.0 {
+            self.costs = other.costs;
+        }
+        if self.year == 0 {
+            self.year = other.year;
+        }
+        if self.edition.is_empty() {
+            self.edition = other.edition;
+        }
+        if self.language.is_empty() {
+            self.language = other.language;
+        }
+        if self.pages == 0 {
+            self.pages = other.pages;
+        }
+        if self.series.is_empty() {
+            self.series = other.series;
+        }
+        if self.subjects.is_empty() {
+            self.subjects = other.subjects;
+        }
+        if self.ddc.is_empty() {
+            self.ddc = other.ddc;
+        }
+        if self.cover.is_empty() {
+            self.cover = other.cover;
+        }
+    }
+    /// The optional fields (edition, series, ...) are not required.
+    fn is_complete(&self) -> bool {
+        !self.title.is_empty()
+            && !self.authors.is_empty()
+            && !self.publisher.is_empty()
+            && self.costs != 0.0
+            && self.year != 0
+    }
+}
+
+/// External source for book metadata
+#[metadata]
+#[repr(i64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Provider {
+    /// Deutsche Nationalbibliothek
+    Dnb,
+    /// Union catalogue of the GBV and SWB library networks
+    K10plus,
+    /// Open Library of the Internet Archive
+    OpenLibrary,
+    /// Google Books
+    GoogleBooks,
+}
+
+impl Provider {
+    /// All providers in their default order
+    pub const ALL: [Provider; 4] = [
+        Provider::Dnb,
+        Provider::K10plus,
+        Provider::OpenLibrary,
+        Provider::GoogleBooks,
+    ];
+
+    /// Fetch the book data from this provider.
+    pub async fn fetch(self, client: &Client, isbn: &str) -> Result<BookData> {
+        match self {
+            Provider::Dnb => dnb::fetch(client, isbn).await,
+            Provider::K10plus => k10plus::fetch(client, isbn).await,
+            Provider::OpenLibrary => openlibrary::fetch(client, isbn).await,
+            Provider::GoogleBooks => google::fetch(client, isbn).await,
+        }
+    }
+}
+
+/// Fetch the book data from the providers in the given order.
+///
+/// Fields missing from the first results are filled by the following providers.
+pub async fn fetch(client: &Client, providers: &[Provider], isbn: &str) -> Result<BookData> {
+    let mut data: Option<BookData> = None;
+    let mut error = Error::NothingFound;
+
+    for provider in providers {
+        match provider.fetch(client, isbn).await {
+            Ok(result) => match &mut data {
+                Some(data) => data.merge(result),
+                None => data = Some(result),
+            },
+            Err(e) => {
+                warn!("Provider {provider:?} failed for {isbn}: {e:?}");
+                if !matches!(e, Error::NothingFound) {
+                    error = e;
+                }
+            }
+        }
+        if data.as_ref().is_some_and(BookData::is_complete) {
+            break;
+        }
+    }
+    data.ok_or(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_book_data() {
+        let mut data = BookData {
+            title: "Der Hobbit".into(),
+            authors: vec![],
+            publisher: "Klett-Cotta".into(),
+            year: 2012,
+            ..BookData::default()
+        };
+        assert!(!data.is_complete());
+        data.merge(BookData {
+            title: "The Hobbit".into(),
+            authors: vec!["J. R. R. Tolkien".into()],
+            publisher: "HarperCollins".into(),
+            costs: 9.49,
+            year: 1995,
+            pages: 310,
+            ..BookData::default()
+        });
+        assert!(data.is_complete());
+        assert_eq!(
+            data,
+            BookData {
+                title: "Der Hobbit".into(),
+                authors: vec!["J. R. R. Tolkien".into()],
+                publisher: "Klett-Cotta".into(),
+                costs: 9.49,
+                year: 2012,
+                pages: 310,
+                ..BookData::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use tracing::info;
+
+use super::BookData;
+use super::marc::parse_year;
+use crate::db::Author;
+use crate::error::{Error, Result};
+
+const URL: &str = "https://openlibrary.org/api/books?format=json&jscmd=data";
+
+#[derive(Deserialize)]
+struct Edition {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    authors: Vec<Named>,
+    #[serde(default)]
+    publishers: Vec<Named>,
+    #[serde(default)]
+    publish_date: String,
+    #[serde(default)]
+    edition_name: String,
+    #[serde(default)]
+    number_of_pages: u32,
+    #[serde(default)]
+    subjects: Vec<Named>,
+    #[serde(default)]
+    classifications: Classifications,
+    #[serde(default)]
+    cover: Option<Cover>,
+}
+
+#[derive(Deserialize)]
+struct Named {
+    name: String,
+}
+
+#[derive(Default, Deserialize)]
+struct Classifications {
+    #[serde(default)]
+    dewey_decimal_class: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Cover {
+    medium: String,
+}
+
+/// Try fetching the book data from the Open Library
+///
+/// ## See Also
+/// https://openlibrary.org/dev/docs/api/books
+pub async fn fetch(client: &Client, isbn: &str) -> Result<BookData> {
+    let url = Url::parse_with_params(URL, [("bibkeys", &format!("ISBN:{isbn}"))])
+        .map_err(|_| Error::Arguments)?;
+    info!("Fetch {url}");
+    let response = client.get(url).send().await?;
+    if !response.status().is_success() {
+        return Err(Error::Network);
+    }
+    let text = response.text().await?;
+    parse(&text, isbn)
+}
+
+fn parse(response: &str, isbn: &str) -> Result<BookData> {
+    let mut editions: HashMap<String, Edition> = serde_json::from_str(response)?;
+    let edition = editions
+        .remove(&format!("ISBN:{isbn}"))
+        .or_else(|| editions.into_values().next())
+        .ok_or(Error::NothingFound)?;
+
+    Ok(BookData {
+        title: edition.title.trim().into(),
+        authors: edition
+            .authors
+            .into_iter()
+            .map(|a| Author::parse(&a.name))
+            .collect(),
+        publisher: edition
+            .publishers
+            .into_iter()
+            .next()
+            .map(|p| p.name.trim().into())
+            .unwrap_or_default(),
+        costs: 0.0,
+        year: parse_year(&edition.publish_date),
+        edition: edition.edition_name.trim().into(),
+        language: String::new(),
+        pages: edition.number_of_pages,
+        series: String::new(),
+        subjects: edition
+            .subjects
+            .into_iter()
+            .map(|s| s.name.trim().into())
+            .collect(),
+        ddc: edition
+            .classifications
+            .dewey_decimal_class
+            .into_iter()
+            .next()
+            .unwrap_or_default(),
+        cover: edition.cover.map(|c| c.medium).unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn parse_edition() {
+        let response =
+            fs::read_to_string("test/openlibrary/openlibrary-response_9780261102217.json").unwrap();
+        let data = parse(&response, "9780261102217").unwrap();
+        assert_eq!(
+            data,
+            BookData {
+                title: "The Hobbit".into(),
+                authors: vec!["J.R.R. Tolkien".into()],
+                publisher: "HarperCollins".into(),
+                costs: 0.0,
+                year: 1995,
+                pages: 310,
+                subjects: vec![
+                    "Fantasy fiction".into(),
+                    "Middle Earth (Imaginary place)".into()
+                ],
+                cover: "https://covers.openlibrary.org/b/id/6979861-M.jpg".into(),
+                ..BookData::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_empty() {
+        let response =
+            fs::read_to_string("test/openlibrary/openlibrary-response_empty.json").unwrap();
+        assert!(matches!(
+            parse(&response, "9780261102217"),
+            Err(Error::NothingFound)
+        ));
+    }
+}
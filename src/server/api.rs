@@ -1,23 +1,33 @@
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
+use axum::body::Bytes;
 use axum::extract::{FromRef, Path, Query, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
 use axum::middleware::from_extractor_with_state;
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
-use chrono::NaiveDate;
+use chrono::{Local, NaiveDate};
 use gluer::{generate, metadata};
 use hyper::StatusCode;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use tracing::error;
+use tokio::task::JoinSet;
+use tracing::{error, warn};
 
 use super::auth::{Auth, Login};
 use crate::db::*;
 use crate::error::{Error, Result};
+use crate::export::{self, Columns, Format};
+use crate::isbn;
+use crate::labels::{self, Label, Layout};
 use crate::mail::{self, account_is_valid};
-use crate::provider;
-use crate::provider::dnb::BookData;
+use crate::provider::BookData;
+use crate::provider::cache::Cache;
+use crate::provider::dnb::{self, Lookup};
+use crate::provider::marc::{self, MarcFormat, Record};
 
 /// User configuration.
 #[derive(Debug, Clone)]
@@ -26,13 +36,21 @@ pub struct UserConfig {
     pub delimiter: u8,
 }
 
+/// Timeout for requests to the metadata providers
+const PROVIDER_TIMEOUT: Duration = Duration::from_secs(3);
+/// Interval for checking if the daily backup is due
+const BACKUP_CHECK: Duration = Duration::from_secs(60 * 60);
+
 /// Project state.
 #[derive(Debug, Clone)]
 pub struct Project {
     db: Arc<AtomicDatabase>,
     user: Option<Arc<UserConfig>>,
     client: Client,
+    cache: Arc<Cache>,
     auth: Auth,
+    /// Other libraries of this server, for the union catalogue
+    libraries: Vec<(String, Arc<AtomicDatabase>)>,
 }
 
 impl FromRef<Project> for Auth {
@@ -43,13 +61,30 @@ impl FromRef<Project> for Auth {
 
 impl Project {
     pub fn new(db: AtomicDatabase, user: Option<UserConfig>, auth: Auth) -> Self {
+        let cache = Cache::load(&db.path().with_extension("cache.json"));
         Self {
             db: Arc::new(db),
             user: user.map(Arc::new),
-            client: Client::new(),
+            client: Client::builder()
+                .timeout(PROVIDER_TIMEOUT)
+                .build()
+                .expect("invalid http client"),
+            cache: Arc::new(cache),
             auth,
+            libraries: Vec::new(),
         }
     }
+
+    /// Database of this library.
+    pub fn db(&self) -> &Arc<AtomicDatabase> {
+        &self.db
+    }
+
+    /// Sets the other libraries of this server, that are searched by the union catalogue.
+    pub fn with_libraries(mut self, libraries: Vec<(String, Arc<AtomicDatabase>)>) -> Self {
+        self.libraries = libraries;
+        self
+    }
 }
 
 pub fn routes(state: Project) -> Router {
@@ -65,7 +100,15 @@ pub fn routes(state: Project) -> Router {
             "/book" = get(book_search).post(book_add),
             "/book/{id}" = get(book_fetch).post(book_update).delete(book_delete),
             "/book-id" = post(book_generate_id),
+            "/book-reid" = post(book_reid),
+            "/book-reid-preview" = post(book_reid_preview),
+            "/book-duplicates" = post(book_duplicates),
+            "/book-merge" = post(book_merge),
             "/book-fetch/{isbn}" = get(book_fetch_data),
+            "/book-lookup" = get(book_lookup),
+            "/book-prefetch" = post(book_prefetch),
+            "/isbn/{isbn}" = get(isbn_info),
+            "/catalogue" = get(catalogue_search),
             // user
             "/user" = get(user_search).post(user_add),
             "/user/{account}" = get(user_fetch).post(user_update).delete(user_delete),
@@ -80,20 +123,49 @@ pub fn routes(state: Project) -> Router {
             "/lending/return" = post(lending_return),
             "/lending/reserve" = post(lending_reserve),
             "/lending/release" = post(lending_release),
+            "/lending/scan" = post(lending_scan),
             "/overdues" = get(lending_overdues),
+            // reports
+            "/reports/loans" = get(reports_loans),
+            "/reports/summary" = get(reports_summary),
+            "/reports/never-borrowed" = get(reports_never_borrowed),
+            // weeding
+            "/weeding" = get(weeding),
+            "/weeding/withdraw" = post(weeding_withdraw),
+            // inventory
+            "/stocktaking" = get(stocktaking_report).post(stocktaking_start).delete(stocktaking_finish),
+            "/stocktaking/scan" = post(stocktaking_scan),
+            "/stocktaking/lost" = post(stocktaking_lost),
             // mail
             "/notify" = post(mail_notify),
+            // backup
+            "/backup" = get(backup_list).post(backup_create),
+            "/backup/{name}/restore" = post(backup_restore),
         },
         files = [
             "src/db",
             "src/server",
             "src/error.rs",
-            "src/provider/dnb.rs"
+            "src/isbn.rs",
+            "src/provider"
         ],
         output = "lib-view/src/lib/api.ts",
     }
+    // downloads are not part of the generated api
+    .route("/export/books", get(export_books))
+    .route("/export/users", get(export_users))
+    .route("/export/overdues", get(export_overdues))
+    .route("/export/inventory", get(export_inventory))
+    .route("/export/weeding", get(export_weeding))
+    .route("/export/marc", get(export_marc))
+    .route("/import/marc", post(import_marc))
+    .route("/backup/{name}", get(backup_download))
+    .route("/labels/books", get(labels_books))
+    .route("/labels/users", get(labels_users))
     // all routes require authorization
     .route_layer(from_extractor_with_state::<Login, Auth>(state.auth.clone()))
+    // searched by other libraries, if enabled in the settings
+    .route("/public/catalogue", get(catalogue_public))
     .fallback(|| async { (StatusCode::NOT_FOUND, Json(Error::NothingFound)) })
     .with_state(state)
 }
@@ -137,7 +209,14 @@ async fn settings_update(
     State(project): State<Project>,
     Json(settings): Json<Settings>,
 ) -> Result<Json<Settings>> {
-    project.db.write().settings_update(settings).map(Json)
+    let (settings, providers) = project.db.transaction(|db| {
+        let providers = db.settings().providers;
+        Ok((db.settings_update(settings)?, providers))
+    })?;
+    if settings.providers != providers {
+        project.cache.clear().await;
+    }
+    Ok(Json(settings))
 }
 
 /// Returns the project statistics.
@@ -187,10 +266,49 @@ async fn book_search(
 }
 
 /// Adds a new book.
+///
+/// Possible duplicates are only logged, see `book_duplicates`.
 #[metadata(custom = [Result])]
 async fn book_add(State(project): State<Project>, Json(book): Json<Book>) -> Result<Json<Book>> {
-    let db = &mut *project.db.write();
-    Ok(Json(db.books.add(book, &db.categories, &db.users)?))
+    let book = project.db.transaction(|db| {
+        let duplicates = db.books.duplicates(&book);
+        if !duplicates.is_empty() {
+            let ids: Vec<_> = duplicates.iter().map(|b| b.id.as_str()).collect();
+            warn!("Adding possible duplicate of {ids:?}: {:?}", book.title);
+        }
+        db.books.add(book, &db.categories, &db.users)
+    })?;
+    Ok(Json(book))
+}
+
+/// Returns the books with the same isbn or a similar title and authors.
+#[metadata(custom = [Result])]
+async fn book_duplicates(
+    State(project): State<Project>,
+    Json(book): Json<Book>,
+) -> Result<Json<Vec<Book>>> {
+    Ok(Json(project.db.read().books.duplicates(&book)))
+}
+
+#[metadata]
+#[derive(Debug, Deserialize)]
+struct MergeParams {
+    /// Book that is kept
+    id: String,
+    /// Book that is merged and removed
+    source: String,
+}
+
+/// Merges two books, filling the missing fields of `id` with the ones from `source`.
+#[metadata(custom = [Result])]
+async fn book_merge(
+    State(project): State<Project>,
+    Query(params): Query<MergeParams>,
+) -> Result<Json<Book>> {
+    let book = project
+        .db
+        .transaction(|db| db.merge_books(&params.id, &params.source))?;
+    Ok(Json(book))
 }
 
 /// Updates the book and all references if its id changes.
@@ -200,15 +318,24 @@ async fn book_update(
     Path(id): Path<String>,
     Json(book): Json<Book>,
 ) -> Result<Json<Book>> {
-    let db = &mut *project.db.write();
-    Ok(Json(db.books.update(&id, book, &db.categories)?))
+    let book = project.db.transaction(|db| {
+        let book = db.books.update(&id, book, &db.categories, &db.users)?;
+        if book.id != id.trim() {
+            db.rename_references(&[IdChange {
+                old: id.trim().into(),
+                new: book.id.clone(),
+            }]);
+        }
+        Ok(book)
+    })?;
+    Ok(Json(book))
 }
 
 /// Deletes the book including the its authors.
 /// Also borrowers & reservations for this book are removed.
 #[metadata(custom = [Result])]
 async fn book_delete(State(project): State<Project>, Path(id): Path<String>) -> Result<()> {
-    project.db.write().books.delete(&id)
+    project.db.transaction(|db| db.books.delete(&id))
 }
 
 /// Generates a new book id.
@@ -217,16 +344,190 @@ async fn book_generate_id(
     State(project): State<Project>,
     Json(book): Json<Book>,
 ) -> Result<Json<String>> {
-    Ok(Json(project.db.write().books.generate_id(&book)?))
+    let db = project.db.read();
+    Ok(Json(db.books.generate_id(
+        &book,
+        &db.settings(),
+        &db.categories,
+    )?))
+}
+
+/// Returns the book ids that would change with the id scheme of the given settings.
+#[metadata(custom = [Result])]
+async fn book_reid_preview(
+    State(project): State<Project>,
+    Json(settings): Json<Settings>,
+) -> Result<Json<Vec<IdChange>>> {
+    let db = project.db.read();
+    Ok(Json(db.books.reid(&settings, &db.categories)?))
+}
+
+/// Changes the ids of all books to the configured id scheme.
+///
+/// Returns the number of changed ids.
+#[metadata(custom = [Result])]
+async fn book_reid(State(project): State<Project>) -> Result<Json<usize>> {
+    let count = project.db.transaction(|db| {
+        let changes = db.books.reid(&db.settings(), &db.categories)?;
+        db.books.rename(&changes)?;
+        db.rename_references(&changes);
+        Ok(changes.len())
+    })?;
+    Ok(Json(count))
 }
 
 /// Fetch the data of the book from the DNB an their like.
+///
+/// The providers are queried in the order specified in the settings.
+/// The results, also missing books, are cached.
 #[metadata(custom = [Result])]
 async fn book_fetch_data(
     State(project): State<Project>,
     Path(isbn): Path<String>,
 ) -> Result<Json<BookData>> {
-    Ok(Json(provider::dnb::fetch(&project.client, &isbn).await?))
+    let providers = project.db.read().settings().providers;
+    Ok(Json(
+        project
+            .cache
+            .fetch(&project.client, &providers, &isbn)
+            .await?,
+    ))
+}
+
+/// Fetch the data of multiple books in the background to warm up the cache.
+///
+/// Returns the number of books that are not cached yet.
+#[metadata(custom = [Result])]
+async fn book_prefetch(
+    State(project): State<Project>,
+    Json(isbns): Json<Vec<String>>,
+) -> Result<Json<usize>> {
+    let providers = project.db.read().settings().providers;
+    let missing = project.cache.missing(&isbns);
+    let count = missing.len();
+    if count > 0 {
+        tokio::spawn(async move {
+            project
+                .cache
+                .prefetch(&project.client, &providers, missing)
+                .await
+        });
+    }
+    Ok(Json(count))
+}
+
+/// Search the DNB by title, author and year for books without isbn.
+///
+/// The records are ranked by their similarity to the search parameters.
+#[metadata(custom = [Result])]
+async fn book_lookup(
+    State(project): State<Project>,
+    Query(params): Query<Lookup>,
+) -> Result<Json<Vec<Record>>> {
+    Ok(Json(dnb::lookup(&project.client, &params).await?))
+}
+
+/// Validates the isbn and returns its alternative forms.
+///
+/// Also detects ISMNs, ISSNs and other EAN-13 codes.
+#[metadata]
+async fn isbn_info(Path(isbn): Path<String>) -> Json<isbn::Info> {
+    Json(isbn::info(&isbn))
+}
+
+// Catalogue
+
+/// Maximum number of titles of a catalogue search
+const CATALOGUE_LIMIT: usize = 50;
+
+/// Catalogue search parameters
+#[metadata]
+#[derive(Debug, Clone, Deserialize)]
+struct CatalogueSearch {
+    query: String,
+}
+
+/// Results of the union catalogue
+#[metadata]
+#[derive(Debug, Serialize)]
+struct UnionSearch {
+    results: Vec<UnionTitle>,
+    /// Remote catalogues that could not be searched
+    unreachable: Vec<String>,
+}
+
+/// Searches this library together with the other libraries of this server,
+/// that have made their catalogue public, and the remote catalogues from the settings.
+#[metadata(custom = [Result])]
+async fn catalogue_search(
+    State(project): State<Project>,
+    Query(params): Query<CatalogueSearch>,
+) -> Result<Json<UnionSearch>> {
+    let query = params.query.trim().to_string();
+    let mut hits = project.db.read().catalogue(&query, CATALOGUE_LIMIT);
+    for (name, db) in &project.libraries {
+        let db = db.read();
+        if db.settings().catalogue_public {
+            hits.extend(library_hits(name, db.catalogue(&query, CATALOGUE_LIMIT)));
+        }
+    }
+
+    let mut requests = JoinSet::new();
+    for remote in project.db.read().settings().catalogues {
+        let client = project.client.clone();
+        let query = query.clone();
+        requests.spawn(async move {
+            let hits = remote_catalogue(&client, &remote.url, &query).await;
+            (remote.name, hits)
+        });
+    }
+    let mut unreachable = Vec::new();
+    while let Some(result) = requests.join_next().await {
+        match result {
+            Ok((name, Ok(remote))) => hits.extend(library_hits(&name, remote)),
+            Ok((name, Err(e))) => {
+                warn!("Catalogue {name:?} is unreachable: {e:?}");
+                unreachable.push(name);
+            }
+            Err(e) => error!("Catalogue search failed: {e}"),
+        }
+    }
+    unreachable.sort();
+
+    Ok(Json(UnionSearch {
+        results: union_catalogue(hits, CATALOGUE_LIMIT),
+        unreachable,
+    }))
+}
+
+/// Searches the catalogue of this library for other libraries.
+///
+/// Only available if the catalogue is public.
+async fn catalogue_public(
+    State(project): State<Project>,
+    Query(params): Query<CatalogueSearch>,
+) -> Result<Json<Vec<CatalogueHit>>> {
+    let db = project.db.read();
+    if !db.settings().catalogue_public {
+        return Err(Error::NothingFound);
+    }
+    Ok(Json(db.catalogue(&params.query, CATALOGUE_LIMIT)))
+}
+
+async fn remote_catalogue(client: &Client, url: &str, query: &str) -> Result<Vec<CatalogueHit>> {
+    let response = client
+        .get(format!("{url}/api/public/catalogue"))
+        .query(&[("query", query)])
+        .send()
+        .await?;
+    Ok(response.error_for_status()?.json().await?)
+}
+
+fn library_hits(name: &str, hits: Vec<CatalogueHit>) -> impl Iterator<Item = CatalogueHit> {
+    hits.into_iter().map(move |mut hit| {
+        hit.holding.library = name.to_string();
+        hit
+    })
 }
 
 // User
@@ -252,7 +553,7 @@ async fn user_search(
 /// Adds a new user.
 #[metadata(custom = [Result])]
 async fn user_add(State(project): State<Project>, Json(user): Json<User>) -> Result<Json<User>> {
-    Ok(Json(project.db.write().users.add(user)?))
+    Ok(Json(project.db.transaction(|db| db.users.add(user))?))
 }
 
 /// Updates the user and all references if its account changes.
@@ -262,8 +563,10 @@ async fn user_update(
     Path(account): Path<String>,
     Json(user): Json<User>,
 ) -> Result<Json<User>> {
-    let db = &mut *project.db.write();
-    Ok(Json(db.users.update(&account, user, &mut db.books)?))
+    let user = project
+        .db
+        .transaction(|db| db.users.update(&account, user, &mut db.books))?;
+    Ok(Json(user))
 }
 
 /// Deletes the user.
@@ -271,8 +574,9 @@ async fn user_update(
 /// Returns a `Error::StillReferenced` if there are any borrows or reservations left.
 #[metadata(custom = [Result])]
 async fn user_delete(State(project): State<Project>, Path(account): Path<String>) -> Result<()> {
-    let db = &mut *project.db.write();
-    db.users.delete(&account, &db.books)
+    project
+        .db
+        .transaction(|db| db.users.delete(&account, &db.books))
 }
 
 /// Fetch the data of the user from the specified user file.
@@ -299,7 +603,10 @@ async fn user_fetch_data(
 async fn user_update_roles(State(project): State<Project>) -> Result<()> {
     if let Some(user) = &project.user {
         let users = super::provider::user::load_roles(&user.file, user.delimiter)?;
-        project.db.write().users.update_roles(users.into_iter())
+        project.db.backup(BackupKind::Import)?;
+        project
+            .db
+            .transaction(|db| db.users.update_roles(users.into_iter()))
     } else {
         Err(Error::NothingFound)
     }
@@ -319,7 +626,9 @@ async fn category_add(
     State(project): State<Project>,
     Json(category): Json<Category>,
 ) -> Result<Json<Category>> {
-    Ok(Json(project.db.write().categories.add(category)?))
+    Ok(Json(
+        project.db.transaction(|db| db.categories.add(category))?,
+    ))
 }
 
 /// Updates the category and all references.
@@ -329,15 +638,18 @@ async fn category_update(
     Path(id): Path<String>,
     Json(category): Json<Category>,
 ) -> Result<Json<Category>> {
-    let db = &mut *project.db.write();
-    Ok(Json(db.categories.update(&id, category, &mut db.books)?))
+    let category = project
+        .db
+        .transaction(|db| db.categories.update(&id, category, &mut db.books))?;
+    Ok(Json(category))
 }
 
 /// Removes the category or returns a `Error::StillReferenced` if it is still in use.
 #[metadata(custom = [Result])]
 async fn category_delete(State(project): State<Project>, Path(id): Path<String>) -> Result<()> {
-    let db = &mut *project.db.write();
-    db.categories.delete(&id, &db.books)
+    project
+        .db
+        .transaction(|db| db.categories.delete(&id, &db.books))
 }
 
 /// Returns the number of books in this category.
@@ -366,11 +678,9 @@ async fn lending_lend(
     State(project): State<Project>,
     Query(params): Query<LendParams>,
 ) -> Result<Json<Book>> {
-    Ok(Json(project.db.write().lend(
-        &params.id,
-        &params.account,
-        params.deadline,
-    )?))
+    Ok(Json(project.db.transaction(|db| {
+        db.lend(&params.id, &params.account, params.deadline)
+    })?))
 }
 #[metadata]
 #[derive(Debug, Deserialize)]
@@ -384,7 +694,9 @@ async fn lending_return(
     State(project): State<Project>,
     Query(params): Query<ReturnParams>,
 ) -> Result<Json<Book>> {
-    Ok(Json(project.db.write().return_back(&params.id)?))
+    Ok(Json(
+        project.db.transaction(|db| db.return_back(&params.id))?,
+    ))
 }
 #[metadata]
 #[derive(Debug, Deserialize)]
@@ -399,9 +711,9 @@ async fn lending_reserve(
     State(project): State<Project>,
     Query(params): Query<ReserveParams>,
 ) -> Result<Json<Book>> {
-    Ok(Json(
-        project.db.write().reserve(&params.id, &params.account)?,
-    ))
+    Ok(Json(project.db.transaction(|db| {
+        db.reserve(&params.id, &params.account)
+    })?))
 }
 
 /// Removes the reservation from the specified book.
@@ -410,7 +722,30 @@ async fn lending_release(
     State(project): State<Project>,
     Query(params): Query<ReturnParams>,
 ) -> Result<Json<Book>> {
-    Ok(Json(project.db.write().release(&params.id)?))
+    Ok(Json(project.db.transaction(|db| db.release(&params.id))?))
+}
+
+#[metadata]
+#[derive(Debug, Deserialize)]
+struct ScanParams {
+    /// Empty to return all books
+    account: String,
+    /// Renew books already borrowed by the user instead of returning them
+    renew: bool,
+}
+
+/// Lends or returns the scanned books in one go.
+///
+/// The deadline is computed from the borrowing duration of the settings.
+#[metadata(custom = [Result])]
+async fn lending_scan(
+    State(project): State<Project>,
+    Query(params): Query<ScanParams>,
+    Json(ids): Json<Vec<String>>,
+) -> Result<Json<Vec<Scan>>> {
+    Ok(Json(project.db.transaction(|db| {
+        db.scan(&params.account, &ids, params.renew)
+    })?))
 }
 
 /// Returns the list of expired borrowing periods.
@@ -419,6 +754,209 @@ async fn lending_overdues(State(project): State<Project>) -> Result<Json<Vec<Ove
     Ok(Json(project.db.read().overdues()?))
 }
 
+// Reports
+
+#[metadata]
+#[derive(Debug, Deserialize)]
+struct ReportParams {
+    /// First day of the period
+    #[meta(optional, into = String)]
+    from: Option<NaiveDate>,
+    /// Last day of the period
+    #[meta(optional, into = String)]
+    to: Option<NaiveDate>,
+}
+
+#[metadata]
+#[derive(Debug, Deserialize)]
+struct LoanReportParams {
+    group: LoanGroup,
+    #[meta(optional, into = String)]
+    from: Option<NaiveDate>,
+    #[meta(optional, into = String)]
+    to: Option<NaiveDate>,
+    /// Maximum number of results, all if zero
+    #[meta(optional)]
+    #[serde(default)]
+    limit: usize,
+}
+
+/// Returns the number of loans per month, category, section, role, or title.
+#[metadata(custom = [Result])]
+async fn reports_loans(
+    State(project): State<Project>,
+    Query(params): Query<LoanReportParams>,
+) -> Result<Json<Vec<LoanCount>>> {
+    let db = project.db.read();
+    let period = Period {
+        from: params.from,
+        to: params.to,
+    };
+    let mut counts = db
+        .loans
+        .count(params.group, period, &db.books, &db.categories);
+    if params.limit > 0 {
+        counts.truncate(params.limit);
+    }
+    Ok(Json(counts))
+}
+
+/// Returns the average loan duration and the overdue rate.
+#[metadata(custom = [Result])]
+async fn reports_summary(
+    State(project): State<Project>,
+    Query(params): Query<ReportParams>,
+) -> Result<Json<LoanSummary>> {
+    let period = Period {
+        from: params.from,
+        to: params.to,
+    };
+    let today = Local::now().date_naive();
+    Ok(Json(project.db.read().loans.summary(period, today)))
+}
+
+#[metadata]
+#[derive(Debug, Deserialize)]
+struct NeverBorrowedParams {
+    years: u32,
+}
+
+/// Returns the books that were not borrowed in the last years.
+#[metadata(custom = [Result])]
+async fn reports_never_borrowed(
+    State(project): State<Project>,
+    Query(params): Query<NeverBorrowedParams>,
+) -> Result<Json<Vec<Book>>> {
+    let db = project.db.read();
+    let today = Local::now().date_naive();
+    Ok(Json(db.loans.never_borrowed(
+        params.years,
+        &db.books,
+        today,
+    )))
+}
+
+// Weeding
+
+/// Returns the books that are suggested for removal.
+#[metadata(custom = [Result])]
+async fn weeding(State(project): State<Project>) -> Result<Json<Vec<WeedingCandidate>>> {
+    Ok(Json(project.db.read().weeding()))
+}
+
+/// Removes the given books from the library, keeping their loan history.
+#[metadata(custom = [Result])]
+async fn weeding_withdraw(
+    State(project): State<Project>,
+    Json(ids): Json<Vec<String>>,
+) -> Result<Json<usize>> {
+    Ok(Json(project.db.transaction(|db| db.withdraw(&ids))?))
+}
+
+// Inventory
+
+/// Returns the report of the running inventory.
+#[metadata(custom = [Result])]
+async fn stocktaking_report(State(project): State<Project>) -> Result<Json<StocktakingReport>> {
+    Ok(Json(project.db.read().stocktaking_report()?))
+}
+
+/// Starts a new inventory for the given categories, all if empty.
+#[metadata(custom = [Result])]
+async fn stocktaking_start(
+    State(project): State<Project>,
+    Json(categories): Json<Vec<String>>,
+) -> Result<Json<StocktakingReport>> {
+    Ok(Json(
+        project
+            .db
+            .transaction(|db| db.stocktaking_start(categories))?,
+    ))
+}
+
+/// Ends the running inventory.
+#[metadata(custom = [Result])]
+async fn stocktaking_finish(State(project): State<Project>) -> Result<()> {
+    project.db.transaction(|db| db.stocktaking_finish())
+}
+
+#[metadata]
+#[derive(Debug, Deserialize)]
+struct StocktakingScanParams {
+    /// Category of the shelf, empty if unknown
+    category: String,
+}
+
+/// Records the scanned or uploaded ids.
+#[metadata(custom = [Result])]
+async fn stocktaking_scan(
+    State(project): State<Project>,
+    Query(params): Query<StocktakingScanParams>,
+    Json(ids): Json<Vec<String>>,
+) -> Result<Json<StocktakingReport>> {
+    Ok(Json(project.db.transaction(|db| {
+        db.stocktaking_scan(&params.category, &ids)
+    })?))
+}
+
+/// Marks the missing books of the running inventory as lost.
+#[metadata(custom = [Result])]
+async fn stocktaking_lost(State(project): State<Project>) -> Result<Json<usize>> {
+    Ok(Json(project.db.transaction(|db| db.stocktaking_lost())?))
+}
+
+// Backup
+
+/// Returns the database snapshots, the newest first.
+#[metadata(custom = [Result])]
+async fn backup_list(State(project): State<Project>) -> Result<Json<Vec<Backup>>> {
+    Ok(Json(project.db.backups().list()?))
+}
+
+/// Takes a snapshot of the database.
+#[metadata(custom = [Result])]
+async fn backup_create(State(project): State<Project>) -> Result<Json<Backup>> {
+    Ok(Json(project.db.backup(BackupKind::Manual)?))
+}
+
+/// Replaces the database with the snapshot, if it is valid.
+///
+/// The current database is saved as snapshot before.
+#[metadata(custom = [Result])]
+async fn backup_restore(State(project): State<Project>, Path(name): Path<String>) -> Result<()> {
+    project.db.restore(&name)?;
+    project.cache.clear().await; // the providers may have changed
+    Ok(())
+}
+
+/// Downloads the snapshot file.
+async fn backup_download(
+    State(project): State<Project>,
+    Path(name): Path<String>,
+) -> Result<Response> {
+    let body = std::fs::read(project.db.backups().path(&name)?)?;
+    let disposition = format!("attachment; filename=\"{name}\"");
+    Ok((
+        [
+            (CONTENT_TYPE, "application/json".to_string()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Takes the daily snapshots.
+pub async fn backup_background(project: Project) {
+    let mut timer = tokio::time::interval(BACKUP_CHECK);
+    loop {
+        timer.tick().await;
+        if let Err(e) = project.db.backup_daily() {
+            error!("Daily backup failed: {e:?}");
+        }
+    }
+}
+
 // Mail Notifications
 #[metadata]
 #[derive(Debug, Deserialize)]
@@ -433,7 +971,7 @@ async fn mail_notify(
     State(project): State<Project>,
     Json(messages): Json<Vec<Message>>,
 ) -> Result<()> {
-    let settings = project.db.read().settings();
+    let settings = project.db.read().mail_settings().clone();
 
     for Message {
         account,
@@ -463,3 +1001,250 @@ async fn mail_notify(
     }
     Ok(())
 }
+
+// Export
+
+/// Export parameters, the filters are the same as for the book search.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct BookExport {
+    query: String,
+    category: String,
+    state: BookState,
+    /// Comma separated list of columns
+    columns: String,
+    format: Format,
+}
+
+/// Exports the books matching the search.
+async fn export_books(
+    State(project): State<Project>,
+    Query(params): Query<BookExport>,
+) -> Result<Response> {
+    let columns = export::columns::<Book>(&params.columns)?;
+    let (_, books) = project.db.read().books.search(&BookSearch {
+        query: params.query,
+        category: params.category,
+        state: params.state,
+        offset: 0,
+        limit: usize::MAX,
+    })?;
+    download("books", params.format, &columns, &books)
+}
+
+/// Export parameters, the filters are the same as for the user search.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct UserExport {
+    query: String,
+    may_borrow: Option<bool>,
+    /// Comma separated list of columns
+    columns: String,
+    format: Format,
+}
+
+/// Exports the users matching the search.
+async fn export_users(
+    State(project): State<Project>,
+    Query(params): Query<UserExport>,
+) -> Result<Response> {
+    let columns = export::columns::<User>(&params.columns)?;
+    let (_, users) = project.db.read().users.search(&UserSearch {
+        query: params.query,
+        may_borrow: params.may_borrow,
+        offset: 0,
+        limit: usize::MAX,
+    })?;
+    download("users", params.format, &columns, &users)
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ExportParams {
+    /// Comma separated list of columns
+    columns: String,
+    format: Format,
+}
+
+/// Exports the list of expired borrowing periods.
+async fn export_overdues(
+    State(project): State<Project>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response> {
+    let columns = export::columns::<Overdue>(&params.columns)?;
+    let overdues = project.db.read().overdues()?;
+    download("overdues", params.format, &columns, &overdues)
+}
+
+/// Exports the number and value of the books per category.
+async fn export_inventory(
+    State(project): State<Project>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response> {
+    let columns = export::columns::<InventoryValue>(&params.columns)?;
+    let inventory = project.db.read().inventory()?;
+    download("inventory", params.format, &columns, &inventory)
+}
+
+/// Exports the books that are suggested for removal.
+async fn export_weeding(
+    State(project): State<Project>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response> {
+    let columns = export::columns::<WeedingCandidate>(&params.columns)?;
+    let candidates = project.db.read().weeding();
+    download("weeding", params.format, &columns, &candidates)
+}
+
+/// Export parameters, the filters are the same as for the book search.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct MarcExport {
+    query: String,
+    category: String,
+    state: BookState,
+    format: MarcFormat,
+}
+
+/// Exports the books matching the search as MARC records.
+async fn export_marc(
+    State(project): State<Project>,
+    Query(params): Query<MarcExport>,
+) -> Result<Response> {
+    let (_, books) = project.db.read().books.search(&BookSearch {
+        query: params.query,
+        category: params.category,
+        state: params.state,
+        offset: 0,
+        limit: usize::MAX,
+    })?;
+    let mut body = Vec::new();
+    marc::write(&mut body, params.format, &books)?;
+    let name = format!("books.{}", params.format.extension());
+    Ok(attachment(&name, params.format.content_type(), body))
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportParams {
+    /// Category of the imported books
+    category: String,
+}
+
+/// Imports the books from a MARC21-xml or ISO 2709 file.
+///
+/// The book ids are generated and records without a title are skipped.
+async fn import_marc(
+    State(project): State<Project>,
+    Query(params): Query<ImportParams>,
+    file: Bytes,
+) -> Result<Json<Vec<Book>>> {
+    let records = marc::parse_file(&file)?;
+
+    project.db.backup(BackupKind::Import)?;
+    let books = project
+        .db
+        .transaction(|db| db.import(records, params.category.trim()))?;
+    Ok(Json(books))
+}
+
+/// Label parameters for books, all filters are optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct BookLabels {
+    layout: Layout,
+    /// Number of already used labels on the first sheet
+    skip: usize,
+    /// First id of the range (inclusive)
+    from: String,
+    /// Last id of the range (inclusive)
+    to: String,
+    category: String,
+    /// Books added on or after this date
+    added_since: Option<NaiveDate>,
+}
+
+/// Returns printable labels with a barcode of the book id.
+async fn labels_books(
+    State(project): State<Project>,
+    Query(params): Query<BookLabels>,
+) -> Result<Response> {
+    let (from, to, category) = (params.from.trim(), params.to.trim(), params.category.trim());
+    let db = project.db.read();
+    let mut books = db
+        .books
+        .data
+        .values()
+        .filter(|b| from.is_empty() || cmp_ids(&b.id, from).is_ge())
+        .filter(|b| to.is_empty() || cmp_ids(&b.id, to).is_le())
+        .filter(|b| category.is_empty() || b.category == category)
+        .filter(|b| {
+            params
+                .added_since
+                .is_none_or(|d| b.added.is_some_and(|a| a >= d))
+        })
+        .collect::<Vec<_>>();
+    books.sort_by(|a, b| cmp_ids(&a.id, &b.id));
+    let labels = books.into_iter().map(Label::from).collect::<Vec<_>>();
+    if labels.is_empty() {
+        return Err(Error::NothingFound);
+    }
+    Ok(html(labels::html(params.layout, params.skip, &labels)))
+}
+
+/// Label parameters for library cards, the filters are the same as for the user search.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct UserLabels {
+    layout: Layout,
+    /// Number of already used labels on the first sheet
+    skip: usize,
+    query: String,
+    may_borrow: Option<bool>,
+}
+
+/// Returns printable library cards with a barcode of the user account.
+async fn labels_users(
+    State(project): State<Project>,
+    Query(params): Query<UserLabels>,
+) -> Result<Response> {
+    let (_, users) = project.db.read().users.search(&UserSearch {
+        query: params.query,
+        may_borrow: params.may_borrow,
+        offset: 0,
+        limit: usize::MAX,
+    })?;
+    if users.is_empty() {
+        return Err(Error::NothingFound);
+    }
+    let labels = users.iter().map(Label::from).collect::<Vec<_>>();
+    Ok(html(labels::html(params.layout, params.skip, &labels)))
+}
+
+fn html(body: String) -> Response {
+    ([(CONTENT_TYPE, "text/html; charset=utf-8")], body).into_response()
+}
+
+fn download<T: Columns>(
+    name: &str,
+    format: Format,
+    columns: &[&str],
+    records: &[T],
+) -> Result<Response> {
+    let mut body = Vec::new();
+    export::write(&mut body, format, columns, records)?;
+    let name = format!("{name}.{}", format.extension());
+    Ok(attachment(&name, format.content_type(), body))
+}
+
+/// File download with the given file `name`.
+fn attachment(name: &str, content_type: &str, body: Vec<u8>) -> Response {
+    let disposition = format!("attachment; filename=\"{name}\"");
+    (
+        [
+            (CONTENT_TYPE, content_type.to_string()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
@@ -10,12 +10,15 @@ use axum::body::Body;
 use axum::error_handling::HandleErrorLayer;
 use axum::extract::{Path, State};
 use axum::http::StatusCode;
+use axum::http::header::{CONTENT_TYPE, HOST, SET_COOKIE};
 use axum::middleware::from_extractor_with_state;
-use axum::response::{IntoResponse, Response};
+use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::get;
+use axum_extra::headers::{Cookie, HeaderMapExt};
 use hyper::Request;
 use hyper::body::Incoming;
 use hyper_util::rt::{TokioExecutor, TokioIo};
+use serde::Deserialize;
 use tokio::net::TcpListener;
 use tokio_rustls::TlsAcceptor;
 use tokio_rustls::rustls::ServerConfig;
@@ -37,23 +40,114 @@ mod api;
 use api::Project;
 pub use api::UserConfig;
 
+/// Library served by this server
+pub struct Library {
+    /// Unique name, also used as path prefix to select the library
+    pub name: String,
+    /// Externally visible domain of this library, if it has its own
+    pub domain: Option<String>,
+    pub db: AtomicDatabase,
+    pub auth: Option<AuthConfig>,
+    pub user: Option<UserConfig>,
+}
+
+/// Configuration of a [`Library`], if multiple libraries are served
+#[derive(Debug, Deserialize)]
+pub struct LibraryConfig {
+    /// Unique name, the library is selected with `https://<domain>/<name>/`
+    pub name: String,
+    /// Externally visible domain of this library, if it has its own
+    pub domain: Option<String>,
+    /// Path to the database
+    pub db: PathBuf,
+    /// Path to the oauth config
+    pub auth: Option<PathBuf>,
+    /// Path to the users file
+    pub user_file: Option<PathBuf>,
+    /// CSV row delimiter for the users file
+    #[serde(default = "default_delimiter")]
+    pub user_delimiter: char,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
 /// Start the backend server
 pub async fn start(
     host: SocketAddr,
     domain: &str,
-    db: AtomicDatabase,
+    libraries: Vec<Library>,
     assets: PathBuf,
     tls: Tls,
-    auth: Option<AuthConfig>,
-    user: Option<UserConfig>,
 ) {
     let tls = tls.load_config().expect("invalid TLS config");
-    let auth = Auth::new(domain, auth);
-    let project = Project::new(db, user, auth.clone());
 
-    let app = Router::new()
+    let multiple = libraries.len() > 1;
+    let mut projects: Vec<(String, Option<String>, Auth, Project)> = Vec::new();
+    for library in libraries {
+        let Library {
+            name,
+            domain: library_domain,
+            db,
+            auth,
+            user,
+        } = library;
+        assert!(
+            !multiple || valid_name(&name, &assets) && projects.iter().all(|(n, ..)| *n != name),
+            "Invalid library name: {name:?}"
+        );
+
+        let auth = Auth::new(library_domain.as_deref().unwrap_or(domain), auth);
+        let project = Project::new(db, user, auth.clone());
+        projects.push((name, library_domain, auth, project));
+    }
+
+    // the union catalogue searches all libraries of this server
+    let databases: Vec<_> = projects
+        .iter()
+        .map(|(name, _, _, project)| (name.clone(), project.db().clone()))
+        .collect();
+    let tenants: Vec<_> = projects
+        .into_iter()
+        .map(|(name, domain, auth, project)| {
+            let others = databases.iter().filter(|(n, _)| *n != name).cloned();
+            let project = project.with_libraries(others.collect());
+            tokio::spawn(auth::background(auth.clone()));
+            tokio::spawn(api::backup_background(project.clone()));
+            Tenant {
+                name,
+                domain,
+                app: routes(auth, project, assets.clone()),
+            }
+        })
+        .collect();
+
+    let app = if let [tenant] = &tenants[..] {
+        tenant.app.clone()
+    } else {
+        Router::new()
+            .fallback(dispatch)
+            .with_state(Arc::new(tenants))
+    };
+    let app = app.layer(
+        ServiceBuilder::new()
+            .layer(CompressionLayer::new())
+            .layer(HandleErrorLayer::new(handle_error))
+            .timeout(Duration::from_secs(10))
+            .layer(TraceLayer::new_for_http())
+            .into_inner(),
+    );
+
+    debug!("Listening on {host}");
+    serve(host, tls, app).await.unwrap();
+}
+
+/// Routes of a single library
+fn routes(auth: Auth, project: Project, assets: PathBuf) -> Router {
+    Router::new()
         .nest("/auth", auth::routes(auth.clone()))
-        .nest("/api", api::routes(project.clone()))
+        .nest("/api", api::routes(project))
         .route(
             "/",
             get(static_index)
@@ -64,21 +158,96 @@ pub async fn start(
             "/{*file}",
             get(static_assets)
                 .with_state(assets)
-                .layer(from_extractor_with_state::<Login, Auth>(auth.clone())),
+                .layer(from_extractor_with_state::<Login, Auth>(auth)),
         )
-        .layer(
-            ServiceBuilder::new()
-                .layer(CompressionLayer::new())
-                .layer(HandleErrorLayer::new(handle_error))
-                .timeout(Duration::from_secs(10))
-                .layer(TraceLayer::new_for_http())
-                .into_inner(),
+}
+
+/// Names that cannot be used for libraries, as they are used by the routes
+const RESERVED: [&str; 2] = ["api", "auth"];
+
+/// Library names must not collide with the routes of the web UI.
+fn valid_name(name: &str, assets: &std::path::Path) -> bool {
+    !name.is_empty()
+        && !name.contains(['/', ';', '=', '"', '<', '>', '&'])
+        && !RESERVED.contains(&name)
+        && !assets.join(name).exists()
+        && !assets.join(name).with_extension("html").exists()
+}
+/// Cookie with the name of the selected library
+const LIBRARY_COOKIE: &str = "LIBRARY";
+
+struct Tenant {
+    name: String,
+    domain: Option<String>,
+    app: Router,
+}
+
+/// Forwards the request to the library with the requested domain or the selected one.
+///
+/// The library is selected by visiting `/<name>/`, which is remembered in a cookie,
+/// as the web UI expects to be served from the root.
+/// Only the public api is also served directly under `/<name>/api/public/`.
+async fn dispatch(State(tenants): State<Arc<Vec<Tenant>>>, mut req: Request<Body>) -> Response {
+    let domain = req
+        .uri()
+        .host()
+        .or_else(|| req.headers().get(HOST)?.to_str().ok())
+        .map(|h| h.split(':').next().unwrap_or(h));
+    if let Some(domain) = domain
+        && let Some(tenant) = tenants.iter().find(|t| {
+            t.domain
+                .as_deref()
+                .is_some_and(|d| d.split(':').next() == Some(domain))
+        })
+    {
+        return tenant.app.clone().oneshot(req).await.into_response();
+    }
+
+    let path = req.uri().path();
+    let prefix = path.trim_start_matches('/').split('/').next();
+    if let Some(tenant) = tenants.iter().find(|t| Some(t.name.as_str()) == prefix) {
+        // the public api is used by other servers, which do not keep cookies
+        let rest = &path.trim_start_matches('/')[tenant.name.len()..];
+        if rest.starts_with("/api/public/") {
+            let query = req
+                .uri()
+                .query()
+                .map(|q| format!("?{q}"))
+                .unwrap_or_default();
+            if let Ok(uri) = format!("{rest}{query}").parse() {
+                *req.uri_mut() = uri;
+                return tenant.app.clone().oneshot(req).await.into_response();
+            }
+        }
+        let cookie = format!(
+            "{LIBRARY_COOKIE}={}; Path=/; SameSite=Lax; HttpOnly; Secure",
+            tenant.name
         );
+        return ([(SET_COOKIE, cookie)], Redirect::to("/")).into_response();
+    }
 
-    debug!("Listening on {host}");
+    let selected = req
+        .headers()
+        .typed_get::<Cookie>()
+        .and_then(|c| c.get(LIBRARY_COOKIE).map(str::to_string));
+    if let Some(selected) = selected
+        && let Some(tenant) = tenants.iter().find(|t| t.name == selected)
+    {
+        return tenant.app.clone().oneshot(req).await.into_response();
+    }
 
-    let (_, r) = tokio::join!(auth::background(auth), serve(host, tls, app));
-    r.unwrap();
+    // let the user select a library
+    let mut body = String::from("<!DOCTYPE html><html><body><ul>");
+    for tenant in tenants.iter() {
+        body += &format!("<li><a href=\"/{0}/\">{0}</a></li>", tenant.name);
+    }
+    body += "</ul></body></html>";
+    (
+        StatusCode::NOT_FOUND,
+        [(CONTENT_TYPE, "text/html; charset=utf-8")],
+        body,
+    )
+        .into_response()
 }
 
 async fn handle_error(error: BoxError) -> StatusCode {
@@ -181,3 +350,72 @@ async fn static_assets(
             .into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::{COOKIE, LOCATION};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn dispatch_libraries() {
+        let tenant = |name: &str, domain: Option<&str>| {
+            let body = name.to_string();
+            Tenant {
+                name: name.into(),
+                domain: domain.map(Into::into),
+                app: Router::new()
+                    .route("/api/about", get(|| async { body }))
+                    .route(
+                        "/api/public/catalogue",
+                        get(|uri: axum::http::Uri| async move { uri.to_string() }),
+                    ),
+            }
+        };
+        let app = Router::new().fallback(dispatch).with_state(Arc::new(vec![
+            tenant("primary", Some("primary.example.com")),
+            tenant("secondary", None),
+        ]));
+        let request = |uri: &str, header: Option<(_, &str)>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some((name, value)) = header {
+                request = request.header(name, value);
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+        let body = |response: Response| async {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await;
+            String::from_utf8(bytes.unwrap().to_vec()).unwrap()
+        };
+
+        // by domain
+        let response = request("/api/about", Some((HOST, "primary.example.com:443")))
+            .await
+            .unwrap();
+        assert_eq!(body(response).await, "primary");
+
+        // selected by the path prefix
+        let response = request("/secondary/", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[LOCATION], "/");
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(cookie.starts_with("LIBRARY=secondary;"));
+        let response = request("/api/about", Some((COOKIE, "LIBRARY=secondary")))
+            .await
+            .unwrap();
+        assert_eq!(body(response).await, "secondary");
+
+        // the public api is served without the cookie
+        let response = request("/secondary/api/public/catalogue?query=foo", None)
+            .await
+            .unwrap();
+        assert_eq!(body(response).await, "/api/public/catalogue?query=foo");
+
+        // unknown libraries have to be selected
+        let response = request("/api/about", Some((COOKIE, "LIBRARY=other")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(body(response).await.contains("href=\"/primary/\""));
+    }
+}
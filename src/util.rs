@@ -14,7 +14,8 @@ pub fn logging() {
     use tracing_subscriber::util::SubscriberInitExt;
     tracing_subscriber::registry()
         .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
-        .with(tracing_subscriber::fmt::layer())
+        // keep the standard output for exports
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
 }
 